@@ -0,0 +1,43 @@
+//! Converts an OBJ model into the binary mesh cache format, see `rasta::cache`.
+//!
+//! ```text
+//! rasta-cache <model.obj> [output]
+//! ```
+use std::path::Path;
+
+use rasta::{object::WavefrontModel, wavefront::WavefrontObj};
+
+const USAGE: &str = "Usage: rasta-cache <model.obj> [output]
+
+The output defaults to the model's path with the extension replaced by .rmesh";
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let model = match args.next() {
+        Some(arg) if arg != "-h" && arg != "--help" => arg,
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let output = args.next().unwrap_or_else(|| {
+        Path::new(&model)
+            .with_extension("rmesh")
+            .display()
+            .to_string()
+    });
+
+    let obj = WavefrontObj::from_file(&model, 1.0)?;
+    let textures = obj.load_textures();
+    let model = WavefrontModel::new_with_materials(obj, &textures)?;
+    let bytes = model.to_cache(&textures)?;
+    std::fs::write(&output, &bytes).map_err(|e| e.to_string())?;
+
+    println!(
+        "Wrote {} ({} triangles, {} bytes)",
+        output,
+        model.triangle_count(),
+        bytes.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Offline renderer, renders a single frame of an OBJ (or its mesh cache), glTF, STL or PLY
+//! model into an image file.
+//!
+//! ```text
+//! rasta-render <model.obj|model.rmesh|model.gltf|model.glb|model.stl|model.ply> [options]
+//! ```
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+use rasta::{
+    cache,
+    canvas::Canvas,
+    draw::{Rasterizer, TransparentQueue, Viewport},
+    gltf::GltfScene,
+    image_canvas::ImageCanvas,
+    light::{Light, Shading},
+    material::BlendMode,
+    math::{Mat4, Vec3},
+    mesh::Mesh,
+    object::{Instance, Model, WavefrontModel},
+    ply::load_ply,
+    rasterize::Color,
+    stl::load_stl,
+    texture::Texture,
+    wavefront::WavefrontObj,
+};
+
+const USAGE: &str =
+    "Usage: rasta-render <model.obj|model.rmesh|model.gltf|model.glb|model.stl|model.ply> [options]
+
+Options:
+    -o, --output <path>      Output image, format picked from the extension (default: out.png)
+    -t, --texture <path>     Texture to apply to the model, instead of its materials
+    -c, --camera <x,y,z>     Camera position (default: 0,0,5)
+        --target <x,y,z>     Point the camera looks at (default: 0,0,0)
+    -s, --size <WxH>         Resolution of the image (default: 800x600)
+        --shading <mode>     gouraud or phong (default: phong)
+        --scale <f>          Scale applied to the model's coordinates (default: 1)
+        --color <r,g,b>      Model color when no texture is given (default: 200,200,200)
+        --background <r,g,b> Background color (default: 21,20,28)
+        --opacity <a>        Blend the model with the background, 0 to 1 (default: 1)
+    -h, --help               Print this message";
+
+struct Options {
+    model: String,
+    output: String,
+    texture: Option<String>,
+    camera: Vec3<f32>,
+    target: Vec3<f32>,
+    width: u32,
+    height: u32,
+    shading: Shading,
+    scale: f32,
+    color: Color,
+    background: Color,
+    opacity: f32,
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut model = None;
+        let mut options = Options {
+            model: String::new(),
+            output: "out.png".to_string(),
+            texture: None,
+            camera: Vec3(0.0, 0.0, 5.0),
+            target: Vec3(0.0, 0.0, 0.0),
+            width: 800,
+            height: 600,
+            shading: Shading::Phong,
+            scale: 1.0,
+            color: Color(200, 200, 200),
+            background: Color(21, 20, 28),
+            opacity: 1.0,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "-o" | "--output" => options.output = value()?,
+                "-t" | "--texture" => options.texture = Some(value()?),
+                "-c" | "--camera" => options.camera = parse_vec3(&value()?)?,
+                "--target" => options.target = parse_vec3(&value()?)?,
+                "-s" | "--size" => {
+                    let size = value()?;
+                    let (w, h) = size
+                        .split_once('x')
+                        .ok_or_else(|| format!("Invalid size: {}", size))?;
+                    options.width = parse(w)?;
+                    options.height = parse(h)?;
+                }
+                "--shading" => {
+                    options.shading = match value()?.to_lowercase().as_str() {
+                        "gouraud" | "gourad" => Shading::Gourad,
+                        "phong" => Shading::Phong,
+                        other => return Err(format!("Unknown shading mode: {}", other)),
+                    }
+                }
+                "--scale" => options.scale = parse(&value()?)?,
+                "--color" => options.color = parse_color(&value()?)?,
+                "--background" => options.background = parse_color(&value()?)?,
+                "--opacity" => options.opacity = parse(&value()?)?,
+                other if other.starts_with('-') => {
+                    return Err(format!("Unknown option: {}\n\n{}", other, USAGE))
+                }
+                _ if model.is_none() => model = Some(arg),
+                _ => return Err(format!("Unexpected argument: {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        options.model = model.ok_or_else(|| USAGE.to_string())?;
+        let (c, t) = (&options.camera, &options.target);
+        if (c.0, c.1, c.2) == (t.0, t.1, t.2) {
+            return Err("The camera can't be at its target".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, String> {
+    s.trim()
+        .parse::<T>()
+        .map_err(|_| format!("Invalid number: {}", s))
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3<f32>, String> {
+    let components = s
+        .split(',')
+        .map(parse::<f32>)
+        .collect::<Result<Vec<_>, _>>()?;
+    match components.as_slice() {
+        [x, y, z] => Ok(Vec3(*x, *y, *z)),
+        _ => Err(format!("Expected x,y,z but got: {}", s)),
+    }
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    let components = s
+        .split(',')
+        .map(parse::<u8>)
+        .collect::<Result<Vec<_>, _>>()?;
+    match components.as_slice() {
+        [r, g, b] => Ok(Color(*r, *g, *b)),
+        _ => Err(format!("Expected r,g,b but got: {}", s)),
+    }
+}
+
+fn main() -> Result<(), String> {
+    let options = Options::parse(std::env::args().skip(1))?;
+
+    let mut canvas = ImageCanvas::new(options.width, options.height);
+
+    let texture = options.texture.as_deref().map(Texture::load).transpose()?;
+
+    let extension = Path::new(&options.model)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    if let Some("gltf" | "glb") = extension.as_deref() {
+        let scene = GltfScene::from_file(&options.model, options.scale)?;
+        let textures = scene.load_textures()?;
+        let mut mesh = scene.mesh(&textures)?;
+        if let Some(texture) = &texture {
+            mesh = mesh.with_materials(vec![]).with_texture(texture);
+            for t in &mut mesh.triangles {
+                t.material = None;
+            }
+        }
+        return render(&options, &mut canvas, &mesh);
+    }
+    if let Some("rmesh") = extension.as_deref() {
+        let bytes = std::fs::read(&options.model).map_err(|e| e.to_string())?;
+        let textures = cache::texture_paths(&bytes)?
+            .into_iter()
+            .map(|path| Ok((path.clone(), Texture::load(&path.to_string_lossy())?)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let mut model = WavefrontModel::from_cache(&bytes, &textures)?;
+        if let Some(texture) = &texture {
+            model = model.with_texture(texture);
+        }
+        return render(&options, &mut canvas, &model);
+    }
+    if let Some("stl" | "ply") = extension.as_deref() {
+        let triangles = match extension.as_deref() {
+            Some("stl") => load_stl(&options.model, options.color)?,
+            _ => load_ply(&options.model, options.color)?,
+        };
+        let scale = Mat4::scale(Vec3(options.scale, options.scale, options.scale));
+        let triangles: Vec<_> = triangles
+            .iter()
+            .map(|t| t.transform(&scale, &Mat4::identity()))
+            .collect();
+        let mut mesh = Mesh::from_triangles(&triangles);
+        if let Some(texture) = &texture {
+            mesh = mesh.with_texture(texture);
+        }
+        return render(&options, &mut canvas, &mesh);
+    }
+
+    let obj = WavefrontObj::from_file(&options.model, options.scale)?;
+    let material_textures = obj.load_textures();
+    let model = match &texture {
+        Some(texture) => WavefrontModel::new_with_tex(obj, texture, true)?,
+        None if !obj.materials().is_empty() => {
+            WavefrontModel::new_with_materials(obj, &material_textures)?
+        }
+        None => WavefrontModel::new_with_normals(obj, options.color, false)?,
+    };
+    render(&options, &mut canvas, &model)
+}
+
+fn render<'a, M: Model<'a>>(
+    options: &Options,
+    canvas: &mut ImageCanvas,
+    model: &'a M,
+) -> Result<(), String> {
+    let mut instance = Instance::new(model).shading(options.shading);
+    if options.opacity < 1.0 {
+        instance = instance.blend(BlendMode::Alpha).opacity(options.opacity);
+    }
+    let instance = instance.build();
+
+    let aspect = canvas.height() as f32 / canvas.width() as f32;
+    let view_matrix = Mat4::look_at(
+        options.camera.clone(),
+        options.target.clone(),
+        Vec3(0.0, 1.0, 0.0),
+    );
+    let perspective = Mat4::perspective(-1.0, 1.0, -aspect, aspect, 1.0, 1000.0);
+    let viewport_to_canvas =
+        Mat4::viewport_to_canvas(canvas.width() as f32, canvas.height() as f32, 1.0, 1.0);
+    let projection = viewport_to_canvas * perspective;
+    let viewport = Viewport {
+        cw: canvas.width() as f32,
+        ch: canvas.height() as f32,
+        vw: 1.0,
+        vh: aspect,
+        d: 1.0,
+        far: 1000.0,
+    };
+    let mut raster = Rasterizer::new(
+        viewport,
+        view_matrix,
+        projection,
+        vec![
+            Light::Ambient(0.2, Color::WHITE),
+            Light::Directional(0.8, Color::WHITE, Vec3(-1.0, 1.0, 1.0)),
+        ],
+    );
+
+    raster.clear(canvas, options.background);
+    // Sorts the model's transparent triangles so they blend in the right order
+    let mut transparent = TransparentQueue::new();
+    raster.queue_instance(
+        canvas,
+        &instance,
+        instance.model.texture(),
+        &mut transparent,
+    );
+    raster.render_transparent(canvas, &mut transparent);
+    canvas.draw();
+
+    canvas.save(&options.output)
+}
@@ -0,0 +1,401 @@
+//! Binary mesh cache, a compact form of a [`WavefrontModel`](crate::object::WavefrontModel)
+//! that loads without any parsing.
+//!
+//! All numbers are little endian:
+//!
+//! ```text
+//! magic "RMSH", version u16
+//! vertex count u32, per vertex:
+//!     flags u8 (1: normal, 2: uv, 4: color), position 3 f32,
+//!     [normal 3 i16 (-32767..32767 is -1..1)], [uv 2 f32], [color 3 u8]
+//! triangle count u32, per triangle:
+//!     indices 3 u32, color 3 u8, material u16 (0xffff for none)
+//! part count u32, per part: name (u32 length + utf8), first u32, end u32
+//! material count u32, per material:
+//!     diffuse 3 u8, specular 3 u8, shininess f32, emissive 3 u8, ambient f32,
+//!     alpha cutoff f32, blend u8 (0 opaque, 1 alpha, 2 additive, 3 multiply),
+//!     opacity f32, texture path (u32 length + utf8, empty for none)
+//! ```
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    material::{BlendMode, Material},
+    math::{Vec2, Vec3},
+    mesh::{Mesh, MeshTriangle, Vertex},
+    object::Part,
+    rasterize::Color,
+    texture::Texture,
+};
+
+pub const MAGIC: &[u8; 4] = b"RMSH";
+/// Bumped whenever the layout changes, older caches are rejected and need regenerating
+pub const VERSION: u16 = 3;
+
+const HAS_NORMAL: u8 = 1;
+const HAS_UV: u8 = 2;
+const HAS_COLOR: u8 = 4;
+const NO_MATERIAL: u16 = u16::MAX;
+
+/// Serialize a mesh and its parts. The materials' textures are stored as their path in
+/// `textures`, textures that aren't in there are left out. Fails when there are too many
+/// materials for a triangle to refer to.
+pub fn encode(
+    mesh: &Mesh,
+    parts: &[Part],
+    textures: &HashMap<PathBuf, Texture>,
+) -> Result<Vec<u8>, String> {
+    let mut out = MAGIC.to_vec();
+    out.extend(VERSION.to_le_bytes());
+
+    out.extend((mesh.vertices.len() as u32).to_le_bytes());
+    for v in &mesh.vertices {
+        let flags = v.normal.as_ref().map_or(0, |_| HAS_NORMAL)
+            | v.uv.map_or(0, |_| HAS_UV)
+            | v.color.map_or(0, |_| HAS_COLOR);
+        out.push(flags);
+        write_vec3(&mut out, &v.position);
+        if let Some(n) = &v.normal {
+            for c in [n.0, n.1, n.2] {
+                out.extend(((c.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes());
+            }
+        }
+        if let Some(uv) = v.uv {
+            out.extend(uv.0.to_le_bytes());
+            out.extend(uv.1.to_le_bytes());
+        }
+        if let Some(color) = v.color {
+            write_color(&mut out, color);
+        }
+    }
+
+    out.extend((mesh.triangles.len() as u32).to_le_bytes());
+    for t in &mesh.triangles {
+        for i in t.indices {
+            out.extend(i.to_le_bytes());
+        }
+        write_color(&mut out, t.color);
+        let material = match t.material {
+            None => NO_MATERIAL,
+            Some(m) => u16::try_from(m)
+                .ok()
+                .filter(|&m| m != NO_MATERIAL)
+                .ok_or_else(|| format!("Material index {} doesn't fit in a mesh cache", m))?,
+        };
+        out.extend(material.to_le_bytes());
+    }
+
+    out.extend((parts.len() as u32).to_le_bytes());
+    for part in parts {
+        write_str(&mut out, &part.name);
+        out.extend((part.triangles.start as u32).to_le_bytes());
+        out.extend((part.triangles.end as u32).to_le_bytes());
+    }
+
+    out.extend((mesh.materials.len() as u32).to_le_bytes());
+    for m in &mesh.materials {
+        write_color(&mut out, m.diffuse);
+        write_color(&mut out, m.specular);
+        out.extend(m.shininess.to_le_bytes());
+        write_color(&mut out, m.emissive);
+        out.extend(m.ambient.to_le_bytes());
+        out.extend(m.alpha_cutoff.to_le_bytes());
+        out.push(match m.blend {
+            BlendMode::Opaque => 0,
+            BlendMode::Alpha => 1,
+            BlendMode::Additive => 2,
+            BlendMode::Multiply => 3,
+        });
+        out.extend(m.opacity.to_le_bytes());
+        let path = m.texture.and_then(|texture| {
+            textures
+                .iter()
+                .find(|(_, t)| std::ptr::eq(*t, texture))
+                .map(|(path, _)| path.to_string_lossy())
+        });
+        write_str(&mut out, path.as_deref().unwrap_or(""));
+    }
+
+    Ok(out)
+}
+
+/// Load a mesh and its parts, the materials' textures are looked up in `textures` by
+/// path, see [`texture_paths`]
+pub fn decode<'a>(
+    bytes: &[u8],
+    textures: &'a HashMap<PathBuf, Texture>,
+) -> Result<(Mesh<'a>, Vec<Part>), String> {
+    let mut r = Reader::new(bytes)?;
+
+    let vertex_count = r.u32()? as usize;
+    let mut vertices = Vec::with_capacity(vertex_count.min(bytes.len()));
+    for _ in 0..vertex_count {
+        let flags = r.u8()?;
+        let position = r.vec3()?;
+        let normal = match flags & HAS_NORMAL {
+            0 => None,
+            _ => {
+                let mut c = || Ok::<_, String>(r.i16()? as f32 / 32767.0);
+                Some(Vec3(c()?, c()?, c()?).normalize())
+            }
+        };
+        let uv = match flags & HAS_UV {
+            0 => None,
+            _ => Some(Vec2(r.f32()?, r.f32()?)),
+        };
+        let color = match flags & HAS_COLOR {
+            0 => None,
+            _ => Some(r.color()?),
+        };
+        vertices.push(Vertex {
+            position,
+            normal,
+            uv,
+            color,
+        });
+    }
+
+    let triangle_count = r.u32()? as usize;
+    let mut triangles = Vec::with_capacity(triangle_count.min(bytes.len()));
+    for _ in 0..triangle_count {
+        let indices = [r.u32()?, r.u32()?, r.u32()?];
+        if indices.iter().any(|&i| i as usize >= vertices.len()) {
+            return Err("Mesh cache has an out of range vertex index".to_string());
+        }
+        triangles.push(MeshTriangle {
+            indices,
+            color: r.color()?,
+            material: match r.u16()? {
+                NO_MATERIAL => None,
+                m => Some(m as usize),
+            },
+        });
+    }
+
+    let part_count = r.u32()? as usize;
+    let mut parts = Vec::with_capacity(part_count.min(bytes.len()));
+    for _ in 0..part_count {
+        let name = r.string()?;
+        let (start, end) = (r.u32()? as usize, r.u32()? as usize);
+        if start > end || end > triangles.len() {
+            return Err("Mesh cache has an out of range part".to_string());
+        }
+        parts.push(Part {
+            name,
+            triangles: start..end,
+        });
+    }
+
+    let material_count = r.u32()? as usize;
+    let mut materials = Vec::with_capacity(material_count.min(bytes.len()));
+    for _ in 0..material_count {
+        materials.push(Material {
+            diffuse: r.color()?,
+            specular: r.color()?,
+            shininess: r.f32()?,
+            emissive: r.color()?,
+            ambient: r.f32()?,
+            alpha_cutoff: r.f32()?,
+            blend: match r.u8()? {
+                0 => BlendMode::Opaque,
+                1 => BlendMode::Alpha,
+                2 => BlendMode::Additive,
+                3 => BlendMode::Multiply,
+                blend => return Err(format!("Unknown blend mode {} in mesh cache", blend)),
+            },
+            opacity: r.f32()?,
+            texture: match r.string()? {
+                path if path.is_empty() => None,
+                path => textures.get(Path::new(&path)),
+            },
+        });
+    }
+    if triangles
+        .iter()
+        .any(|t| t.material.is_some_and(|m| m >= materials.len()))
+    {
+        return Err("Mesh cache has an out of range material index".to_string());
+    }
+
+    Ok((
+        Mesh::new(vertices, triangles).with_materials(materials),
+        parts,
+    ))
+}
+
+/// Paths of the textures the cached materials use, so they can be loaded before
+/// [`decode`]
+pub fn texture_paths(bytes: &[u8]) -> Result<Vec<PathBuf>, String> {
+    let mut r = Reader::new(bytes)?;
+    for _ in 0..r.u32()? {
+        let flags = r.u8()?;
+        let size = 12
+            + if flags & HAS_NORMAL != 0 { 6 } else { 0 }
+            + if flags & HAS_UV != 0 { 8 } else { 0 }
+            + if flags & HAS_COLOR != 0 { 3 } else { 0 };
+        r.take(size)?;
+    }
+    let triangle_count = r.u32()? as usize;
+    let triangle_size = triangle_count
+        .checked_mul(17)
+        .ok_or_else(|| "Mesh cache is corrupt".to_string())?;
+    r.take(triangle_size)?;
+    for _ in 0..r.u32()? {
+        r.string()?;
+        r.take(8)?;
+    }
+    let mut paths = vec![];
+    for _ in 0..r.u32()? {
+        r.take(3 + 3 + 4 + 3 + 4 + 4 + 1 + 4)?;
+        let path = r.string()?;
+        if !path.is_empty() && !paths.iter().any(|p: &PathBuf| p == Path::new(&path)) {
+            paths.push(PathBuf::from(path));
+        }
+    }
+    Ok(paths)
+}
+
+fn write_vec3(out: &mut Vec<u8>, v: &Vec3<f32>) {
+    for c in [v.0, v.1, v.2] {
+        out.extend(c.to_le_bytes());
+    }
+}
+
+fn write_color(out: &mut Vec<u8>, color: Color) {
+    out.extend([color.0, color.1, color.2]);
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend((s.len() as u32).to_le_bytes());
+    out.extend(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Check the header, leaving the reader at the vertices
+    fn new(bytes: &'a [u8]) -> Result<Self, String> {
+        let mut r = Self { bytes };
+        if r.take(4)? != MAGIC {
+            return Err("Not a mesh cache".to_string());
+        }
+        match r.u16()? {
+            VERSION => Ok(r),
+            version => Err(format!(
+                "Mesh cache version {} is not supported (expected {}), regenerate it",
+                version, VERSION
+            )),
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("Unexpected end of mesh cache".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3<f32>, String> {
+        Ok(Vec3(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn color(&mut self) -> Result<Color, String> {
+        let [r, g, b] = self.array()?;
+        Ok(Color(r, g, b))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::Triangle;
+
+    #[test]
+    fn round_trip() {
+        let mut triangle = Triangle::new(
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(1.0, 0.0, 0.0),
+            Vec3(0.0, 1.0, 0.0),
+            Color::RED,
+            Some([
+                Vec3(0.0, 0.0, 1.0),
+                Vec3(0.0, 0.0, 1.0),
+                Vec3(0.0, 0.0, 1.0),
+            ]),
+        );
+        triangle.uvs = Some([Vec2(0.0, 0.0), Vec2(1.0, 0.0), Vec2(0.0, 1.0)]);
+        let mut mesh = Mesh::from_triangles(&[triangle]);
+        mesh.set_material(
+            0..1,
+            Material::new(Color::GREEN).specular(Color::BLACK, 0.0),
+        );
+        let parts = vec![Part {
+            name: "tri".to_string(),
+            triangles: 0..1,
+        }];
+
+        let bytes = encode(&mesh, &parts, &HashMap::new()).unwrap();
+        let textures = HashMap::new();
+        let (decoded, decoded_parts) = decode(&bytes, &textures).unwrap();
+
+        assert_eq!(decoded.triangles, mesh.triangles);
+        assert_eq!(decoded_parts, parts);
+        let t = decoded.triangle(0);
+        assert_eq!((t.p1.0, t.uvs.unwrap()[2].1), (1.0, 1.0));
+        assert_eq!(t.normals.unwrap()[0].2, 1.0);
+        assert_eq!(decoded.materials[0].diffuse, Color::GREEN);
+        assert_eq!(texture_paths(&bytes).unwrap(), Vec::<PathBuf>::new());
+
+        let mut old = bytes.clone();
+        old[4] = 0;
+        assert!(decode(&old, &textures).unwrap_err().contains("version"));
+
+        let parts = vec![Part {
+            name: "tri".to_string(),
+            triangles: 0..2,
+        }];
+        let bytes = encode(&mesh, &parts, &HashMap::new()).unwrap();
+        assert!(decode(&bytes, &textures).unwrap_err().contains("part"));
+
+        mesh.triangles[0].material = Some(1);
+        let bytes = encode(&mesh, &[], &HashMap::new()).unwrap();
+        assert!(decode(&bytes, &textures).unwrap_err().contains("material"));
+        mesh.triangles[0].material = Some(u16::MAX as usize);
+        assert!(encode(&mesh, &[], &HashMap::new()).is_err());
+    }
+}
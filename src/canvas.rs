@@ -2,6 +2,12 @@ use crate::rasterize::Color;
 
 pub trait Canvas {
     fn put_pixel<X: IntoPixelValue, Y: IntoPixelValue>(&mut self, x: X, y: Y, color: Color);
+    /// The color at the same coordinates as [`Canvas::put_pixel`], `None` outside of the
+    /// canvas. Blending needs it, canvases that can't read their pixels back keep the
+    /// default, and skip blended pixels.
+    fn get_pixel<X: IntoPixelValue, Y: IntoPixelValue>(&self, _x: X, _y: Y) -> Option<Color> {
+        None
+    }
 
     /// Display the contents of the offscreen buffer into the canvas.
     fn draw(&mut self);
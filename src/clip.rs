@@ -0,0 +1,247 @@
+use crate::{
+    math::{Vec2, Vec3, Vec4},
+    rasterize::Point,
+};
+
+/// Vertex in clip space (after projection but before the divide by w), along with the
+/// attributes that need to be interpolated when a triangle is clipped.
+#[derive(Clone, Debug)]
+pub struct ClipVertex {
+    pub clip: Vec4<f32>,
+    /// Position in view space
+    pub view: Vec3<f32>,
+    pub uv: Option<Vec2<f32>>,
+    pub normal: Option<Vec3<f32>>,
+    /// Vertex color as floats, so it can be interpolated
+    pub color: Option<Vec3<f32>>,
+}
+
+impl ClipVertex {
+    /// Divide by w, giving the vertex's position on the canvas
+    pub fn project(&self) -> Point {
+        Point {
+            x: (self.clip.0 / self.clip.3).floor(),
+            y: (self.clip.1 / self.clip.3).floor(),
+        }
+    }
+
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            clip: Vec4(
+                lerp(self.clip.0, other.clip.0, t),
+                lerp(self.clip.1, other.clip.1, t),
+                lerp(self.clip.2, other.clip.2, t),
+                lerp(self.clip.3, other.clip.3, t),
+            ),
+            view: lerp_vec3(&self.view, &other.view, t),
+            uv: match (&self.uv, &other.uv) {
+                (Some(a), Some(b)) => Some(Vec2(lerp(a.0, b.0, t), lerp(a.1, b.1, t))),
+                _ => None,
+            },
+            normal: match (&self.normal, &other.normal) {
+                (Some(a), Some(b)) => Some(lerp_vec3(a, b, t)),
+                _ => None,
+            },
+            color: match (&self.color, &other.color) {
+                (Some(a), Some(b)) => Some(lerp_vec3(a, b, t)),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Plane {
+    Near,
+    Far,
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl Plane {
+    const ALL: [Plane; 6] = [
+        Plane::Near,
+        Plane::Far,
+        Plane::Left,
+        Plane::Right,
+        Plane::Bottom,
+        Plane::Top,
+    ];
+}
+
+/// The view frustum in clip space.
+///
+/// The camera looks down -z and the projection copies z into w, so w is *negative* for
+/// points in front of the camera. x and y are in canvas units, meaning a point is on
+/// screen when `|x / w| <= half_width` and `|y / w| <= half_height`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub near: f32,
+    pub far: f32,
+    pub half_width: f32,
+    pub half_height: f32,
+}
+
+impl Frustum {
+    pub fn new(near: f32, far: f32, half_width: f32, half_height: f32) -> Self {
+        Self {
+            near,
+            far,
+            half_width,
+            half_height,
+        }
+    }
+
+    /// Signed distance of `v` to the plane, positive is inside. It is affine in the
+    /// clip coordinates, so it can be interpolated along an edge.
+    fn distance(&self, plane: Plane, v: &Vec4<f32>) -> f32 {
+        let depth = -v.3;
+        match plane {
+            Plane::Near => depth - self.near,
+            Plane::Far => self.far - depth,
+            Plane::Left => self.half_width * depth + v.0,
+            Plane::Right => self.half_width * depth - v.0,
+            Plane::Bottom => self.half_height * depth + v.1,
+            Plane::Top => self.half_height * depth - v.1,
+        }
+    }
+
+    /// Clip a triangle against all six planes of the frustum. Returns the triangles
+    /// covering the part of the triangle that is inside, which is empty if the triangle
+    /// is completely outside.
+    pub fn clip_triangle(&self, triangle: [ClipVertex; 3]) -> Vec<[ClipVertex; 3]> {
+        let mut fully_inside = true;
+        for plane in Plane::ALL {
+            let inside = triangle
+                .iter()
+                .filter(|v| self.distance(plane, &v.clip) >= 0.0)
+                .count();
+            if inside == 0 {
+                return vec![];
+            }
+            fully_inside &= inside == 3;
+        }
+        if fully_inside {
+            return vec![triangle];
+        }
+
+        // Sutherland-Hodgman, one plane at a time
+        let mut polygon = triangle.to_vec();
+        for plane in Plane::ALL {
+            polygon = self.clip_polygon(plane, &polygon);
+            if polygon.len() < 3 {
+                return vec![];
+            }
+        }
+
+        // The clipped polygon is convex, so a fan keeps the winding of the triangle
+        let mut triangles = Vec::with_capacity(polygon.len() - 2);
+        for i in 1..polygon.len() - 1 {
+            triangles.push([
+                polygon[0].clone(),
+                polygon[i].clone(),
+                polygon[i + 1].clone(),
+            ]);
+        }
+        triangles
+    }
+
+    fn clip_polygon(&self, plane: Plane, polygon: &[ClipVertex]) -> Vec<ClipVertex> {
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+
+        for (i, current) in polygon.iter().enumerate() {
+            let next = &polygon[(i + 1) % polygon.len()];
+            let d_current = self.distance(plane, &current.clip);
+            let d_next = self.distance(plane, &next.clip);
+
+            if d_current >= 0.0 {
+                clipped.push(current.clone());
+            }
+            if (d_current >= 0.0) != (d_next >= 0.0) {
+                let t = d_current / (d_current - d_next);
+                clipped.push(current.lerp(next, t));
+            }
+        }
+
+        clipped
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lerp_vec3(a: &Vec3<f32>, b: &Vec3<f32>, t: f32) -> Vec3<f32> {
+    Vec3(lerp(a.0, b.0, t), lerp(a.1, b.1, t), lerp(a.2, b.2, t))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Vertex at view space position (x, y, z) with an identity-like projection,
+    /// w = z like the renderer's perspective matrix
+    fn vertex(x: f32, y: f32, z: f32, uv: (f32, f32)) -> ClipVertex {
+        ClipVertex {
+            clip: Vec4(x, y, z, z),
+            view: Vec3(x, y, z),
+            uv: Some(uv.into()),
+            normal: None,
+            color: None,
+        }
+    }
+
+    fn frustum() -> Frustum {
+        Frustum::new(1.0, 100.0, 10.0, 10.0)
+    }
+
+    #[test]
+    fn inside_is_untouched() {
+        let triangle = [
+            vertex(-1.0, 0.0, -5.0, (0.0, 0.0)),
+            vertex(1.0, 0.0, -5.0, (1.0, 0.0)),
+            vertex(0.0, 1.0, -5.0, (0.0, 1.0)),
+        ];
+        let clipped = frustum().clip_triangle(triangle);
+
+        assert_eq!(clipped.len(), 1);
+        assert_eq!(clipped[0][1].view.0, 1.0);
+    }
+
+    #[test]
+    fn behind_camera_is_rejected() {
+        let triangle = [
+            vertex(-1.0, 0.0, 5.0, (0.0, 0.0)),
+            vertex(1.0, 0.0, 5.0, (1.0, 0.0)),
+            vertex(0.0, 1.0, 0.5, (0.0, 1.0)),
+        ];
+
+        assert!(frustum().clip_triangle(triangle).is_empty());
+    }
+
+    #[test]
+    fn crossing_near_plane() {
+        let triangle = [
+            vertex(0.0, 0.0, -3.0, (0.0, 0.0)),
+            vertex(1.0, 0.0, -3.0, (1.0, 0.0)),
+            vertex(0.0, 0.0, 1.0, (0.0, 1.0)),
+        ];
+        let clipped = frustum().clip_triangle(triangle);
+
+        // One vertex outside turns the triangle into a quad
+        assert_eq!(clipped.len(), 2);
+        for v in clipped.iter().flatten() {
+            assert!(v.clip.3 <= -1.0 + f32::EPSILON);
+        }
+
+        // The edge from z = -3 to z = 1 is cut at z = -1, halfway along it
+        let cut = clipped
+            .iter()
+            .flatten()
+            .find(|v| v.view.0 == 0.0 && v.view.2 == -1.0)
+            .unwrap();
+        assert_eq!(cut.uv.unwrap().1, 0.5);
+    }
+}
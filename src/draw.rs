@@ -1,66 +1,114 @@
-use std::ops::{Add, Mul};
+use std::{
+    borrow::Borrow,
+    ops::{Add, Mul},
+};
 
 use crate::{
     canvas::{canvas_coords_to_screen_coords, Canvas, IntoPixelValue},
+    clip::{ClipVertex, Frustum},
     lerp::{triangle_lerp, triangle_lerp_and_calculate_left, Lerp},
-    light::{Light, Shading},
+    light::{Illumination, Light, Shading},
+    material::{BlendMode, Material},
     math::{Degrees, Mat4, Vec2, Vec3, Vec4},
     object::{Cube, Instance, Model, Triangle},
     rasterize::{Color, Point},
     texture::Texture,
 };
 
+/// Size of the canvas and of the viewport it shows, along with the clipping planes
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    /// Canvas width in pixels
+    pub cw: f32,
+    /// Canvas height in pixels
+    pub ch: f32,
+    /// Viewport width
+    pub vw: f32,
+    /// Viewport height
+    pub vh: f32,
+    /// Distance to the near plane
+    pub d: f32,
+    /// Distance to the far plane
+    pub far: f32,
+}
+
 pub struct Rasterizer {
     cw: f32,
     ch: f32,
     vw: f32,
     vh: f32,
     d: f32,
+    far: f32,
     depth_buffer: Vec<f32>,
     pub view_projection_matrix: Mat4<f32>,
     pub view_matrix: Mat4<f32>,
-    unproject_matrix: Mat4<f32>,
     lights: Vec<Light>,
     shading: Shading,
+    /// Transformed vertices of the mesh being rendered, so vertices shared between
+    /// triangles are only transformed once
+    vertex_cache: Vec<Option<ClipVertex>>,
 }
 
 impl Rasterizer {
+    /// Triangles are clipped against the near and far planes of the viewport (and the
+    /// sides of the canvas) before drawing.
     pub fn new(
-        cw: f32,
-        ch: f32,
-        vw: f32,
-        vh: f32,
-        d: f32,
+        viewport: Viewport,
         view_matrix: Mat4<f32>,
         projection_matrix: Mat4<f32>,
         lights: Vec<Light>,
     ) -> Self {
+        let Viewport {
+            cw,
+            ch,
+            vw,
+            vh,
+            d,
+            far,
+        } = viewport;
         Self {
             cw,
             ch,
             vw,
             vh,
             d,
+            far,
             depth_buffer: vec![f32::INFINITY; cw as usize * ch as usize],
             view_projection_matrix: &projection_matrix * &view_matrix,
-            unproject_matrix: &view_matrix.invert().unwrap() * projection_matrix.invert().unwrap(),
             view_matrix,
             lights,
             shading: Shading::Phong,
+            vertex_cache: vec![],
         }
     }
 
-    fn unproject_point(&self, x: f32, y: f32, z: f32) -> Vec3<f32> {
-        let mut unprojected_point = &self.unproject_matrix * Vec4(x, y, z, 1.0);
-        // divide by z
-        unprojected_point.0 = unprojected_point.3;
-        unprojected_point.1 = unprojected_point.3;
-        unprojected_point.2 = unprojected_point.3;
-        Vec3(
-            unprojected_point.0,
-            unprojected_point.1,
-            unprojected_point.2,
-        )
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut Vec<Light> {
+        &mut self.lights
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.lights = lights;
+    }
+
+    /// The lights moved into view space, which is where lighting is computed
+    pub fn view_space_lights(&self) -> Vec<Light> {
+        self.lights
+            .iter()
+            .map(|light| light.transform(&self.view_matrix))
+            .collect()
+    }
+
+    /// The view frustum in clip space, see [`Frustum`]
+    pub fn frustum(&self) -> Frustum {
+        Frustum::new(self.d, self.far, self.cw / 2.0, self.ch / 2.0)
     }
 
     pub fn should_cull(&self, t: &Triangle, camera: Vec3<f32>) -> bool {
@@ -98,6 +146,33 @@ impl Rasterizer {
         }
     }
 
+    /// Like [`Self::put_pixel`], but combines the color with the canvas' one and leaves
+    /// the depth buffer as is, so the surfaces behind still get drawn
+    fn blend_pixel<C, X, Y>(
+        &mut self,
+        canvas: &mut C,
+        x: X,
+        y: Y,
+        inv_z: f32,
+        blend: BlendMode,
+        (color, alpha): (Color, f32),
+    ) where
+        C: Canvas,
+        X: IntoPixelValue,
+        Y: IntoPixelValue,
+    {
+        if let Some((x_screen, y_screen)) =
+            canvas_coords_to_screen_coords(x, y, canvas.width(), canvas.height())
+        {
+            let depth_buffer_idx = (y_screen * canvas.width()) + x_screen;
+            if inv_z < self.depth_buffer[depth_buffer_idx as usize] {
+                if let Some(behind) = canvas.get_pixel(x, y) {
+                    canvas.put_pixel(x, y, blend.blend(color, alpha, behind));
+                }
+            }
+        }
+    }
+
     pub fn viewport_to_canvas(&self, p: Vec2<f32>) -> Vec2<f32> {
         Vec2(
             (p.0 * self.cw / self.vw).floor(),
@@ -255,52 +330,102 @@ impl Rasterizer {
         Self::draw_line(canvas, p2, p0, color)
     }
 
+    /// Illumination of `point` by a single light, see [`Illumination`] for how it's
+    /// combined with the surface color. Everything is in view space, so the camera is at
+    /// the origin.
     pub fn compute_illumination(
-        center: Vec3<f32>,
-        camera_pos: &Vec3<f32>,
-        normal: Vec3<f32>,
+        point: &Vec3<f32>,
+        normal: &Vec3<f32>,
         light: &Light,
-    ) -> f32 {
-        let mut illumination = 0.2;
-        match light {
-            Light::Ambient(_) => todo!(),
-            Light::Directional(intensity, dir) => {
-                // Diffuse component
-                let cos_alpha = f32::max(
-                    0.0,
-                    dir.dot(&normal) / (dir.magnitude() * normal.magnitude()),
-                );
-                illumination += cos_alpha * intensity;
-
-                // Specular component
-                let reflected = &normal * (2.0 * normal.dot(&dir)) + (dir * -1.0);
-                let view = Vec3(0.0, 0.0, 0.0) - center;
-                let cos_beta = reflected.dot(&view) / (reflected.magnitude() * view.magnitude());
-                if cos_beta > 0.0 {
-                    let specular = 50;
-                    illumination += cos_beta.powi(specular) * intensity;
+        material: &Material,
+    ) -> Illumination {
+        let (diffuse, specular) = match light {
+            Light::Ambient(..) => (material.ambient, 0.0),
+            Light::Directional(_, _, dir) => {
+                Self::diffuse_and_specular(point, normal, dir, material.shininess)
+            }
+            Light::Point(_, _, position, attenuation)
+            | Light::Spot {
+                position,
+                attenuation,
+                ..
+            } => {
+                let to_light = position - point;
+                let cone = light.cone_factor(&to_light);
+                if cone <= 0.0 {
+                    return Illumination::NONE;
                 }
+                let factor = cone * attenuation.factor(to_light.magnitude());
+                let (diffuse, specular) =
+                    Self::diffuse_and_specular(point, normal, &to_light, material.shininess);
+                (factor * diffuse, factor * specular)
             }
-            Light::Point(_, _) => todo!(),
         };
-        illumination
+        let radiance = light.radiance();
+        Illumination {
+            diffuse: &radiance * diffuse,
+            specular: radiance * specular,
+        }
+    }
+
+    /// Sum of the illumination of `point` by all the lights
+    pub fn compute_lighting(
+        point: &Vec3<f32>,
+        normal: &Vec3<f32>,
+        lights: &[Light],
+        material: &Material,
+    ) -> Illumination {
+        lights
+            .iter()
+            .fold(Illumination::NONE, |illumination, light| {
+                illumination + Self::compute_illumination(point, normal, light, material)
+            })
     }
 
-    /// Draw triangle by passing in the triangle object and its projected vertices
+    /// Diffuse and specular intensity of a light in the direction `to_light`
+    fn diffuse_and_specular(
+        point: &Vec3<f32>,
+        normal: &Vec3<f32>,
+        to_light: &Vec3<f32>,
+        shininess: f32,
+    ) -> (f32, f32) {
+        let normal = normal.normalize();
+        let to_light = to_light.normalize();
+
+        // Diffuse component
+        let cos_alpha = to_light.dot(&normal);
+        if cos_alpha <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        // Specular component
+        let mut specular = 0.0;
+        let reflected = &normal * (2.0 * cos_alpha) + (to_light * -1.0);
+        let view = Vec3(0.0, 0.0, 0.0) - point.clone();
+        let cos_beta = reflected.dot(&view) / (reflected.magnitude() * view.magnitude());
+        if cos_beta > 0.0 && shininess > 0.0 {
+            specular = cos_beta.powf(shininess);
+        }
+
+        (cos_alpha, specular)
+    }
+
+    /// Draw triangle by passing in the triangle object (in view space), its projected
+    /// vertices, the lights (in view space, see [`Self::view_space_lights`]) and the
+    /// material it's made of
     /// TODO: refactor this
     pub fn draw_triangle<C: Canvas>(
         &mut self,
         canvas: &mut C,
-        center: Vec3<f32>,
-        light_direction: Vec3<f32>,
-        camera_pos: &Vec3<f32>,
+        lights: &[Light],
         triangle: &Triangle,
-        texture: Option<&Texture>,
+        material: &Material,
         mut p0: Point,
         mut p1: Point,
         mut p2: Point,
     ) {
-        if self.should_cull(triangle, center) {
+        // The camera is at the origin in view space
+        if self.should_cull(triangle, Vec3(0.0, 0.0, 0.0)) {
             return;
         }
         let color = triangle.color;
@@ -336,15 +461,6 @@ impl Rasterizer {
             })
             .unwrap_or((normal.clone(), normal.clone(), normal.clone()));
 
-        let light = Light::Directional(
-            if matches!(self.shading, Shading::Phong) {
-                1.9
-            } else {
-                0.9
-            },
-            light_direction,
-        );
-
         // Compute x of triangle edges
         let (x_left, x_right, x02_is_left) =
             triangle_lerp_and_calculate_left(p0.y, p1.y, p2.y, p0.x, p1.x, p2.x);
@@ -352,11 +468,44 @@ impl Rasterizer {
         let (z_left, z_right) =
             triangle_lerp(p0.y, p1.y, p2.y, 1.0 / z0, 1.0 / z1, 1.0 / z2, x02_is_left);
         // Compute points for Gouraud shading
-        let illu0 = Self::compute_illumination(v0.clone(), camera_pos, normal.clone(), &light);
-        let illu1 = Self::compute_illumination(v1.clone(), camera_pos, normal.clone(), &light);
-        let illu2 = Self::compute_illumination(v2.clone(), camera_pos, normal.clone(), &light);
-        let (i_left, i_right) = triangle_lerp(p0.y, p1.y, p2.y, illu0, illu1, illu2, x02_is_left);
-        // Compute points for phong shading
+        let Illumination {
+            diffuse: d0,
+            specular: s0,
+        } = Self::compute_lighting(v0, &n0, lights, material);
+        let Illumination {
+            diffuse: d1,
+            specular: s1,
+        } = Self::compute_lighting(v1, &n1, lights, material);
+        let Illumination {
+            diffuse: d2,
+            specular: s2,
+        } = Self::compute_lighting(v2, &n2, lights, material);
+        let (dr_left, dr_right) = triangle_lerp(p0.y, p1.y, p2.y, d0.0, d1.0, d2.0, x02_is_left);
+        let (dg_left, dg_right) = triangle_lerp(p0.y, p1.y, p2.y, d0.1, d1.1, d2.1, x02_is_left);
+        let (db_left, db_right) = triangle_lerp(p0.y, p1.y, p2.y, d0.2, d1.2, d2.2, x02_is_left);
+        let (sr_left, sr_right) = triangle_lerp(p0.y, p1.y, p2.y, s0.0, s1.0, s2.0, x02_is_left);
+        let (sg_left, sg_right) = triangle_lerp(p0.y, p1.y, p2.y, s0.1, s1.1, s2.1, x02_is_left);
+        let (sb_left, sb_right) = triangle_lerp(p0.y, p1.y, p2.y, s0.2, s1.2, s2.2, x02_is_left);
+        // Compute points for phong shading, the view space position is interpolated
+        // perspective correctly, like the uvs
+        let (px_left, px_right) = triangle_lerp(
+            p0.y,
+            p1.y,
+            p2.y,
+            v0.0 / z0,
+            v1.0 / z1,
+            v2.0 / z2,
+            x02_is_left,
+        );
+        let (py_left, py_right) = triangle_lerp(
+            p0.y,
+            p1.y,
+            p2.y,
+            v0.1 / z0,
+            v1.1 / z1,
+            v2.1 / z2,
+            x02_is_left,
+        );
         let (nx_left, nx_right) = triangle_lerp(p0.y, p1.y, p2.y, n0.0, n1.0, n2.0, x02_is_left);
         let (ny_left, ny_right) = triangle_lerp(p0.y, p1.y, p2.y, n0.1, n1.1, n2.1, x02_is_left);
         let (nz_left, nz_right) = triangle_lerp(p0.y, p1.y, p2.y, n0.2, n1.2, n2.2, x02_is_left);
@@ -392,14 +541,55 @@ impl Rasterizer {
                 Default::default(),
             )
         };
+        // Compute vertex colors, perspective correctly like the uvs
+        let color_lerps = triangle.colors.map(|colors| {
+            let [c0, c1, c2] = [i0, i1, i2].map(|i| colors[i as usize].to_vec3_f32s());
+            [
+                triangle_lerp(
+                    p0.y,
+                    p1.y,
+                    p2.y,
+                    c0.0 / z0,
+                    c1.0 / z1,
+                    c2.0 / z2,
+                    x02_is_left,
+                ),
+                triangle_lerp(
+                    p0.y,
+                    p1.y,
+                    p2.y,
+                    c0.1 / z0,
+                    c1.1 / z1,
+                    c2.1 / z2,
+                    x02_is_left,
+                ),
+                triangle_lerp(
+                    p0.y,
+                    p1.y,
+                    p2.y,
+                    c0.2 / z0,
+                    c1.2 / z1,
+                    c2.2 / z2,
+                    x02_is_left,
+                ),
+            ]
+        });
 
         // Draw
-        let mut y = p0.y;
-        while y <= p2.y {
+        // Pixels are drawn when their coordinates are inside the triangle, with the top and
+        // right edges left out, so neighbouring triangles never draw the same pixel twice
+        // (which would show as seams when blending)
+        let mut y = p0.y.ceil();
+        while y < p2.y {
             let (xl, xr) = (x_left.interpolate(y), x_right.interpolate(y));
 
             let zscan = Lerp::new(xl, z_left.interpolate(y), xr, z_right.interpolate(y));
-            let iscan = Lerp::new(xl, i_left.interpolate(y), xr, i_right.interpolate(y));
+            let drscan = Lerp::new(xl, dr_left.interpolate(y), xr, dr_right.interpolate(y));
+            let dgscan = Lerp::new(xl, dg_left.interpolate(y), xr, dg_right.interpolate(y));
+            let dbscan = Lerp::new(xl, db_left.interpolate(y), xr, db_right.interpolate(y));
+            let srscan = Lerp::new(xl, sr_left.interpolate(y), xr, sr_right.interpolate(y));
+            let sgscan = Lerp::new(xl, sg_left.interpolate(y), xr, sg_right.interpolate(y));
+            let sbscan = Lerp::new(xl, sb_left.interpolate(y), xr, sb_right.interpolate(y));
             let uscan = triangle
                 .uvs
                 .as_ref()
@@ -408,38 +598,100 @@ impl Rasterizer {
                 .uvs
                 .as_ref()
                 .map(|_| Lerp::new(xl, v_left.interpolate(y), xr, v_right.interpolate(y)));
+            // The same one row down, to measure how fast the uvs change vertically, which
+            // along with the change across the scanline picks the texture's mip level
+            let next_scans = triangle.uvs.as_ref().map(|_| {
+                let y = y + 1.0;
+                let (xl, xr) = (x_left.interpolate(y), x_right.interpolate(y));
+                [
+                    Lerp::new(xl, z_left.interpolate(y), xr, z_right.interpolate(y)),
+                    Lerp::new(xl, u_left.interpolate(y), xr, u_right.interpolate(y)),
+                    Lerp::new(xl, v_left.interpolate(y), xr, v_right.interpolate(y)),
+                ]
+            });
+
+            let color_scans = color_lerps.as_ref().map(|lerps| {
+                lerps.each_ref().map(|(left, right)| {
+                    Lerp::new(xl, left.interpolate(y), xr, right.interpolate(y))
+                })
+            });
 
             let nxscan = Lerp::new(xl, nx_left.interpolate(y), xr, nx_right.interpolate(y));
             let nyscan = Lerp::new(xl, ny_left.interpolate(y), xr, ny_right.interpolate(y));
             let nzscan = Lerp::new(xl, nz_left.interpolate(y), xr, nz_right.interpolate(y));
+            let pxscan = Lerp::new(xl, px_left.interpolate(y), xr, px_right.interpolate(y));
+            let pyscan = Lerp::new(xl, py_left.interpolate(y), xr, py_right.interpolate(y));
 
-            let mut x = xl;
-            while x <= xr {
+            let mut x = xl.ceil();
+            while x < xr {
                 let inverse_z = zscan.interpolate(x);
-                let color =
-                    if let (Some(uscan), Some(vscan), Some(texture)) = (&uscan, &vscan, texture) {
-                        texture.texel(
+                let (color, alpha) = if let (Some(uscan), Some(vscan), Some(next), Some(texture)) =
+                    (&uscan, &vscan, &next_scans, material.texture)
+                {
+                    let uv = |[zscan, uscan, vscan]: [&Lerp; 3], x: f32| {
+                        let inverse_z = zscan.interpolate(x);
+                        Vec2(
                             uscan.interpolate(x) / inverse_z,
                             vscan.interpolate(x) / inverse_z,
                         )
-                    } else {
-                        color
                     };
-                let intensity = match self.shading {
-                    Shading::Gourad => iscan.interpolate(x),
+                    let here = uv([&zscan, uscan, vscan], x);
+                    let right = uv([&zscan, uscan, vscan], x + 1.0);
+                    let below = uv(next.each_ref(), x);
+                    let lod = texture.level_of_detail(&right - &here, &below - &here);
+                    texture.texel_rgba(here.0, here.1, lod)
+                } else if let Some([r, g, b]) = &color_scans {
+                    let color = Vec3(r.interpolate(x), g.interpolate(x), b.interpolate(x))
+                        * (1.0 / inverse_z);
+                    (Color::from_vec3_f32s(color), 1.0)
+                } else {
+                    (color, 1.0)
+                };
+                // Alpha testing, the cut out parts of the texture leave the pixel untouched
+                if material.blend == BlendMode::Opaque && alpha < material.alpha_cutoff {
+                    x += 1.0;
+                    continue;
+                }
+                let illumination = match self.shading {
+                    Shading::Gourad => Illumination {
+                        diffuse: Vec3(
+                            drscan.interpolate(x),
+                            dgscan.interpolate(x),
+                            dbscan.interpolate(x),
+                        ),
+                        specular: Vec3(
+                            srscan.interpolate(x),
+                            sgscan.interpolate(x),
+                            sbscan.interpolate(x),
+                        ),
+                    },
                     Shading::Phong => {
-                        let vertex = self.unproject_point(x, y, 1.0 / inverse_z);
+                        let point = Vec3(
+                            pxscan.interpolate(x) / inverse_z,
+                            pyscan.interpolate(x) / inverse_z,
+                            1.0 / inverse_z,
+                        );
                         let normal = Vec3(
                             nxscan.interpolate(x),
                             nyscan.interpolate(x),
                             nzscan.interpolate(x),
                         );
-                        Self::compute_illumination(vertex, camera_pos, normal, &light)
+                        Self::compute_lighting(&point, &normal, lights, material)
                     }
                 };
-                let illuminated_color = Color::from_vec3_f32s(&color.to_vec3_f32s() * intensity);
-
-                self.put_pixel(canvas, x, y, inverse_z, illuminated_color);
+                let illuminated_color = material.shade(color, &illumination);
+
+                match material.blend {
+                    BlendMode::Opaque => self.put_pixel(canvas, x, y, inverse_z, illuminated_color),
+                    blend => self.blend_pixel(
+                        canvas,
+                        x,
+                        y,
+                        inverse_z,
+                        blend,
+                        (illuminated_color, alpha * material.opacity),
+                    ),
+                }
                 x += 1.0;
             }
             y += 1.0;
@@ -633,14 +885,19 @@ impl Rasterizer {
     }
 
     pub fn draw_cube<C: Canvas>(&mut self, canvas: &mut C, cube: &Cube) {
+        let lights = self.view_space_lights();
+        let materials = cube.materials();
+        let default_material = Material::default();
         for triangle in cube.triangles() {
+            let material = triangle
+                .material
+                .and_then(|i| materials.get(i))
+                .unwrap_or(&default_material);
             self.draw_triangle(
                 canvas,
-                Vec3(0.0, 0.0, 0.0),
-                Vec3(0.0, 0.0, 0.0),
-                &Vec3(0.0, 0.0, 0.0),
+                &lights,
                 triangle,
-                None,
+                material,
                 self.project_vertex(triangle.p0.clone()).into(),
                 self.project_vertex(triangle.p1.clone()).into(),
                 self.project_vertex(triangle.p2.clone()).into(),
@@ -668,7 +925,64 @@ impl Rasterizer {
     ) {
         self.shading = instance.shading();
         let instance_matrix = &instance.transform_matrix;
-        self.render_model(canvas, instance.model, instance_matrix, texture);
+        let blending = (instance.blend(), instance.opacity());
+        self.render(
+            canvas,
+            instance.model,
+            instance_matrix,
+            texture,
+            blending,
+            None,
+        );
+    }
+
+    /// Like [`Self::render_instance`], but only the opaque triangles are drawn right away.
+    /// The transparent ones go to `queue`, to be drawn back to front with
+    /// [`Self::render_transparent`] once all the opaque instances are.
+    pub fn queue_instance<'a, 'b, C: Canvas, M: Model<'a>>(
+        &mut self,
+        canvas: &mut C,
+        instance: &Instance<'a, M>,
+        texture: Option<&'b Texture>,
+        queue: &mut TransparentQueue<'b>,
+    ) where
+        'a: 'b,
+    {
+        self.shading = instance.shading();
+        let instance_matrix = &instance.transform_matrix;
+        let blending = (instance.blend(), instance.opacity());
+        self.render(
+            canvas,
+            instance.model,
+            instance_matrix,
+            texture,
+            blending,
+            Some(queue),
+        );
+    }
+
+    /// Draw the queued transparent triangles, furthest first, and empty the queue
+    pub fn render_transparent<C: Canvas>(&mut self, canvas: &mut C, queue: &mut TransparentQueue) {
+        let lights = self.view_space_lights();
+        let shading = self.shading;
+        // The camera looks down -z, so the furthest triangles have the lowest z
+        queue
+            .triangles
+            .sort_by(|a, b| a.depth().total_cmp(&b.depth()));
+        for queued in queue.triangles.drain(..) {
+            self.shading = queued.shading;
+            let [p0, p1, p2] = queued.points;
+            self.draw_triangle(
+                canvas,
+                &lights,
+                &queued.triangle,
+                &queued.material,
+                p0,
+                p1,
+                p2,
+            );
+        }
+        self.shading = shading;
     }
 
     pub fn render_model<'a, 'b, C, M>(
@@ -680,46 +994,385 @@ impl Rasterizer {
     ) where
         C: Canvas,
         M: Model<'a>,
+    {
+        self.render(canvas, model, transform_matrix, texture, (None, 1.0), None);
+    }
+
+    /// Draw the model, `blending` is the instance's blend mode and opacity, which override
+    /// and multiply the materials' ones. Without a `queue` the transparent triangles are
+    /// drawn along with the others.
+    fn render<'a, 'b, 'q, C, M>(
+        &mut self,
+        canvas: &mut C,
+        model: &'a M,
+        transform_matrix: &Mat4<f32>,
+        texture: Option<&'b Texture>,
+        (blend, opacity): (Option<BlendMode>, f32),
+        mut queue: Option<&mut TransparentQueue<'q>>,
+    ) where
+        C: Canvas,
+        M: Model<'a>,
+        'a: 'q,
+        'b: 'q,
     {
         let final_transform = &self.view_projection_matrix * transform_matrix;
-        let mut projected = vec![];
-        for v in model.vertices() {
-            let projected_vertex = &final_transform * Vec4(v.0, v.1, v.2, 1.0);
-            let divide_by_w = Point {
-                x: (projected_vertex.0 / projected_vertex.3).floor(),
-                y: (projected_vertex.1 / projected_vertex.3).floor(),
+        let model_view = &self.view_matrix * transform_matrix;
+        let normal_matrix = model_view
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or_else(|| model_view.clone());
+        let frustum = self.frustum();
+        let lights = self.view_space_lights();
+        // Materials without a texture of their own use the model's texture (if any)
+        let materials: Vec<_> = model
+            .materials()
+            .iter()
+            .map(|material| Material {
+                texture: material.texture.or(texture),
+                blend: blend.unwrap_or(material.blend),
+                opacity: material.opacity * opacity,
+                ..material.clone()
+            })
+            .collect();
+        let default_material = Material {
+            texture,
+            blend: blend.unwrap_or_default(),
+            opacity,
+            ..Default::default()
+        };
+
+        let context = DrawContext {
+            frustum: &frustum,
+            lights: &lights,
+            materials: &materials,
+            default_material: &default_material,
+        };
+
+        if let Some(mesh) = model.mesh() {
+            let mut cache = std::mem::take(&mut self.vertex_cache);
+            cache.clear();
+            cache.resize(mesh.vertices.len(), None);
+            for t in &mesh.triangles {
+                let vertices = t.indices.map(|index| {
+                    cache[index as usize]
+                        .get_or_insert_with(|| {
+                            let vertex = &mesh.vertices[index as usize];
+                            ClipVertex {
+                                clip: &final_transform * vertex.position.to_point_vec4(),
+                                view: (&model_view * vertex.position.to_point_vec4())
+                                    .drop_fourth_component(),
+                                uv: vertex.uv,
+                                normal: vertex.normal.as_ref().map(|normal| {
+                                    (&normal_matrix * normal.to_direction_vec4())
+                                        .drop_fourth_component()
+                                }),
+                                color: vertex.color.map(Color::to_vec3_f32s),
+                            }
+                        })
+                        .clone()
+                });
+                let queue = queue.as_deref_mut();
+                self.clip_and_draw(canvas, &context, vertices, t.color, t.material, queue);
+            }
+            self.vertex_cache = cache;
+            return;
+        }
+
+        for t in model.triangles() {
+            let t = t.borrow();
+            let transformed = t.transform(&model_view, &normal_matrix);
+            let vertices = [0, 1, 2].map(|i| ClipVertex {
+                clip: &final_transform * t[i].to_point_vec4(),
+                view: transformed[i].clone(),
+                uv: t.uvs.map(|uvs| uvs[i as usize]),
+                normal: transformed
+                    .normals
+                    .as_ref()
+                    .map(|normals| normals[i as usize].clone()),
+                color: t.colors.map(|colors| colors[i as usize].to_vec3_f32s()),
+            });
+            let queue = queue.as_deref_mut();
+            self.clip_and_draw(canvas, &context, vertices, t.color, t.material, queue);
+        }
+    }
+
+    fn clip_and_draw<'b, C: Canvas>(
+        &mut self,
+        canvas: &mut C,
+        context: &DrawContext<'_, 'b>,
+        vertices: [ClipVertex; 3],
+        color: Color,
+        material_index: Option<usize>,
+        mut queue: Option<&mut TransparentQueue<'b>>,
+    ) {
+        let material = material_index
+            .and_then(|i| context.materials.get(i))
+            .unwrap_or(context.default_material);
+        // Clip in clip space, before the divide by w, so vertices behind the camera never
+        // get projected
+        for [v0, v1, v2] in context.frustum.clip_triangle(vertices) {
+            let (p0, p1, p2) = (v0.project(), v1.project(), v2.project());
+            let triangle = Triangle {
+                color,
+                material: material_index,
+                uvs: match (v0.uv, v1.uv, v2.uv) {
+                    (Some(uv0), Some(uv1), Some(uv2)) => Some([uv0, uv1, uv2]),
+                    _ => None,
+                },
+                normals: match (v0.normal, v1.normal, v2.normal) {
+                    (Some(n0), Some(n1), Some(n2)) => Some([n0, n1, n2]),
+                    _ => None,
+                },
+                colors: match (v0.color, v1.color, v2.color) {
+                    (Some(c0), Some(c1), Some(c2)) => Some([c0, c1, c2].map(Color::from_vec3_f32s)),
+                    _ => None,
+                },
+                p0: v0.view,
+                p1: v1.view,
+                p2: v2.view,
             };
-            projected.push(divide_by_w);
+
+            match queue.as_deref_mut() {
+                Some(queue) if material.blend != BlendMode::Opaque => {
+                    queue.triangles.push(QueuedTriangle {
+                        triangle,
+                        points: [p0, p1, p2],
+                        material: material.clone(),
+                        shading: self.shading,
+                    })
+                }
+                _ => self.draw_triangle(canvas, context.lights, &triangle, material, p0, p1, p2),
+            }
         }
+    }
+}
 
-        // let transformed_center = &self.view_matrix * Vec4(0.0, 0.0, 0.0, 1.0);
-        let transformed_center = Vec4(0.0, 0.0, 0.0, 1.0);
-        // let light_direction = &self.view_matrix * Vec4(0.6, -0.1, 1.0, 0.0);
-        let light_direction = if self.shading == Shading::Phong {
-            Vec4(-1.5, -1.0, 1.0, 0.0)
-        } else {
-            Vec4(0.0, 0.4, 1.0, 0.0)
+/// Transparent triangles put aside by [`Rasterizer::queue_instance`], they have to be
+/// drawn after the opaque ones and back to front to blend with what's behind them
+#[derive(Default)]
+pub struct TransparentQueue<'a> {
+    triangles: Vec<QueuedTriangle<'a>>,
+}
+
+impl<'a> TransparentQueue<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+}
+
+/// A clipped triangle in view space, ready for [`Rasterizer::draw_triangle`]
+struct QueuedTriangle<'a> {
+    triangle: Triangle,
+    points: [Point; 3],
+    material: Material<'a>,
+    shading: Shading,
+}
+
+impl QueuedTriangle<'_> {
+    /// View space depth of the center, lower is further away
+    fn depth(&self) -> f32 {
+        (self.triangle.p0.2 + self.triangle.p1.2 + self.triangle.p2.2) / 3.0
+    }
+}
+
+/// What [`Rasterizer::render_model`] sets up once per model for drawing its triangles
+struct DrawContext<'a, 'b> {
+    frustum: &'a Frustum,
+    lights: &'a [Light],
+    materials: &'a [Material<'b>],
+    default_material: &'a Material<'b>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{image_canvas::ImageCanvas, light::Attenuation, mesh::Mesh};
+
+    fn quad(z: f32, color: Color) -> Mesh<'static> {
+        let corners =
+            [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| Vec3(x, y, z));
+        let [a, b, c, d] = corners;
+        Mesh::from_triangles(&[
+            Triangle::new(a.clone(), b, c.clone(), color, None),
+            Triangle::new(a, c, d, color, None),
+        ])
+    }
+
+    /// Renders to a 20x20 canvas, with the camera at the origin and a 90 degree field of view
+    fn rasterizer() -> Rasterizer {
+        let projection = &Mat4::viewport_to_canvas(20.0, 20.0, 1.0, 1.0)
+            * &Mat4::perspective(-1.0, 1.0, -1.0, 1.0, 1.0, 100.0);
+        let viewport = Viewport {
+            cw: 20.0,
+            ch: 20.0,
+            vw: 1.0,
+            vh: 1.0,
+            d: 1.0,
+            far: 100.0,
         };
+        Rasterizer::new(
+            viewport,
+            Mat4::identity(),
+            projection,
+            vec![Light::Ambient(1.0, Color::WHITE)],
+        )
+    }
 
-        let mut i = 0;
-        for t in model.triangles() {
-            self.draw_triangle(
-                canvas,
-                Vec3(
-                    transformed_center.0,
-                    transformed_center.1,
-                    transformed_center.2,
-                ),
-                Vec3(light_direction.0, light_direction.1, light_direction.2),
-                &(&self.view_matrix * Vec4(0.0, 0.0, 0.0, 1.0)).drop_fourth_component(),
-                // &t.transform(&(&final_transform)),
-                &t.transform(&(&self.view_matrix * transform_matrix), &final_transform),
-                texture,
-                projected[i].into(),
-                projected[i + 1].into(),
-                projected[i + 2].into(),
-            );
-            i += 3;
+    #[test]
+    fn colored_light() {
+        let red = Light::Directional(1.0, Color::RED, Vec3(0.0, 1.0, 0.0));
+        let white = Material::new(Color::WHITE);
+        let illumination = Rasterizer::compute_illumination(
+            &Vec3(0.0, 0.0, -5.0),
+            &Vec3(0.0, 1.0, 0.0),
+            &red,
+            &white,
+        );
+        let Vec3(r, g, b) = illumination.diffuse;
+        assert_eq!((r, g, b), (1.0, 0.0, 0.0));
+        assert_eq!(white.shade(Color::WHITE, &illumination), Color::RED);
+    }
+
+    #[test]
+    fn point_light_falls_off() {
+        // Straight above a surface facing up, 10 units away
+        let light = Light::Point(
+            1.0,
+            Color::WHITE,
+            Vec3(0.0, 10.0, -5.0),
+            Attenuation::default(),
+        );
+        let illumination = Rasterizer::compute_illumination(
+            &Vec3(0.0, 0.0, -5.0),
+            &Vec3(0.0, 1.0, 0.0),
+            &light,
+            &Material::default(),
+        );
+        assert!((illumination.diffuse.0 - Attenuation::default().factor(10.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn lights_change_the_image() {
+        let quad = quad(-2.0, Color::WHITE);
+        let instance = Instance::new(&quad).build();
+        let render = |raster: &mut Rasterizer| {
+            let mut canvas = ImageCanvas::new(20, 20);
+            raster.clear(&mut canvas, Color::BLACK);
+            raster.render_instance(&mut canvas, &instance, None);
+            canvas.pixel(10, 10).unwrap()
+        };
+
+        let mut raster = rasterizer();
+        raster.set_lights(vec![Light::Ambient(0.25, Color::WHITE)]);
+        let dim = render(&mut raster);
+        // Shining straight at the quad
+        raster.add_light(Light::Directional(0.5, Color::WHITE, Vec3(0.0, 0.0, 1.0)));
+        let lit = render(&mut raster);
+        assert!(lit.0 > dim.0 + 100);
+
+        if let Light::Ambient(intensity, _) = &mut raster.lights_mut()[0] {
+            *intensity = 0.0;
+        }
+        let directional_only = render(&mut raster);
+        assert!(directional_only.0 < lit.0 && directional_only.0 > dim.0);
+    }
+
+    #[test]
+    fn mesh_renders_like_its_triangles() {
+        let corners = [-1.0, 1.0].map(|x| [-1.0, 1.0].map(|y| [-1.0, 1.0].map(|z| Vec3(x, y, z))));
+        let [[[lbb, lbf], [ltb, ltf]], [[rbb, rbf], [rtb, rtf]]] = corners;
+        let colors = [
+            Color::RED,
+            Color::GREEN,
+            Color::BLUE,
+            Color::WHITE,
+            Color(255, 255, 0),
+            Color(0, 255, 255),
+        ];
+        let cube = Cube::new(ltf, lbf, rbf, rtf, ltb, lbb, rbb, rtb, colors);
+        let mesh = cube.to_mesh();
+
+        // Turned so more than one side shows, and clipped by the sides of the canvas
+        let transform = &Mat4::translate(Vec3(0.3, 0.0, -3.0))
+            * &(&Mat4::rotate_y_axis(Degrees(30.0), Vec3(0.0, 0.0, 0.0))
+                * &Mat4::rotate_z_axis(Degrees(20.0), Vec3(0.0, 0.0, 0.0)));
+        let mut triangles = ImageCanvas::new(20, 20);
+        let mut indexed = ImageCanvas::new(20, 20);
+        for canvas in [&mut triangles, &mut indexed] {
+            rasterizer().clear(canvas, Color::BLACK);
+        }
+        rasterizer().render_model(&mut triangles, &cube, &transform, None);
+        rasterizer().render_model(&mut indexed, &mesh, &transform, None);
+
+        assert_eq!(triangles.as_bytes(), indexed.as_bytes());
+        let background = (0..20)
+            .flat_map(|y| (0..20).map(move |x| (x, y)))
+            .filter(|&(x, y)| indexed.pixel(x, y) == Some(Color::BLACK))
+            .count();
+        assert!(background > 0 && background < 300);
+    }
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        // Each half of the quad on a canvas of its own
+        let halves: Vec<ImageCanvas> = quad(-4.0, Color::WHITE)
+            .triangles()
+            .map(|t| {
+                let half = Mesh::from_triangles(&[t.borrow().clone()]);
+                let mut canvas = ImageCanvas::new(20, 20);
+                let mut raster = rasterizer();
+                raster.clear(&mut canvas, Color::BLACK);
+                raster.render_instance(&mut canvas, &Instance::new(&half).build(), None);
+                canvas
+            })
+            .collect();
+
+        // The quad covers pixels 5 to 14, each of them drawn by exactly one half
+        for y in 0..20 {
+            for x in 0..20 {
+                let drawn = halves
+                    .iter()
+                    .filter(|canvas| canvas.pixel(x, y) != Some(Color::BLACK))
+                    .count();
+                let inside = (5..15).contains(&x) && (5..15).contains(&y);
+                assert_eq!(drawn, inside as usize, "pixel {}, {}", x, y);
+            }
         }
     }
+
+    #[test]
+    fn transparent_queue_blends_back_to_front() {
+        let mut canvas = ImageCanvas::new(20, 20);
+        let mut raster = rasterizer();
+        raster.clear(&mut canvas, Color::BLACK);
+
+        let (near, far) = (quad(-2.0, Color::RED), quad(-3.0, Color::BLUE));
+        let near = Instance::new(&near)
+            .blend(BlendMode::Alpha)
+            .opacity(0.5)
+            .build();
+        let far = Instance::new(&far)
+            .blend(BlendMode::Alpha)
+            .opacity(0.5)
+            .build();
+
+        // Queued nearest first, but drawn furthest first
+        let mut queue = TransparentQueue::new();
+        raster.queue_instance(&mut canvas, &near, None, &mut queue);
+        raster.queue_instance(&mut canvas, &far, None, &mut queue);
+        assert_eq!(queue.len(), 4);
+        assert_eq!(canvas.get_pixel(0, 0), Some(Color::BLACK));
+
+        raster.render_transparent(&mut canvas, &mut queue);
+        assert!(queue.is_empty());
+        assert_eq!(canvas.get_pixel(0, 0), Some(Color(127, 0, 63)));
+    }
 }
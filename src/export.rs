@@ -0,0 +1,299 @@
+use std::{borrow::Borrow, collections::HashMap, fs, path::Path};
+
+use crate::{
+    material::Material,
+    math::Mat4,
+    mesh::Mesh,
+    object::{Instance, Model, Triangle},
+    rasterize::Color,
+    texture::Texture,
+};
+
+/// A model converted to OBJ, along with the MTL library its faces use and the textures
+/// that library refers to
+pub struct ObjExport<'a> {
+    pub obj: String,
+    pub mtl: String,
+    /// The file names `map_Kd` refers to, with the textures to save under them
+    pub textures: Vec<(String, &'a Texture)>,
+}
+
+/// Write the model to `path` as OBJ, with its transform baked into the coordinates. The
+/// MTL library goes next to it with the same name, as do the textures (as png files).
+pub fn export_obj<'a, M: Model<'a>, P: AsRef<Path>>(
+    model: &'a M,
+    transform: &Mat4<f32>,
+    path: P,
+) -> Result<(), String> {
+    let path = path.as_ref();
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(file_name)
+        .ok_or_else(|| format!("Invalid obj path: {}", path.display()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let export = to_obj(model, transform, &name);
+    fs::write(path, export.obj).map_err(to_string)?;
+    fs::write(dir.join(format!("{}.mtl", name)), export.mtl).map_err(to_string)?;
+    for (file, texture) in export.textures {
+        texture.save(dir.join(file))?;
+    }
+    Ok(())
+}
+
+/// [`export_obj`] with the instance's transform, so the model ends up where the instance
+/// puts it in the world
+pub fn export_instance<'a, M: Model<'a>, P: AsRef<Path>>(
+    instance: &Instance<'a, M>,
+    path: P,
+) -> Result<(), String> {
+    export_obj(instance.model, &instance.transform_matrix, path)
+}
+
+/// Convert the model to OBJ, `name` is used for the MTL library and texture file names,
+/// with its whitespace replaced by underscores
+pub fn to_obj<'a, M: Model<'a>>(model: &'a M, transform: &Mat4<f32>, name: &str) -> ObjExport<'a> {
+    let name = &file_name(name);
+    let normal_matrix = transform
+        .invert()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or_else(|| transform.clone());
+    // Mirroring transforms flip the winding
+    let mirrored = transform.det() < 0.0;
+    let triangles: Vec<Triangle> = model
+        .triangles()
+        .map(|t| {
+            let mut t = t.borrow().transform(transform, &normal_matrix);
+            if let Some(normals) = &mut t.normals {
+                for normal in normals {
+                    *normal = normal.normalize();
+                }
+            }
+            t
+        })
+        .collect();
+    // Shares the corners between triangles, so every v, vt and vn is only written once
+    let mesh = Mesh::from_triangles(&triangles);
+
+    let mut obj = format!("# Exported from rasta\nmtllib {}.mtl\n", name);
+    let mut uv_indices = Vec::with_capacity(mesh.vertices.len());
+    let mut normal_indices = Vec::with_capacity(mesh.vertices.len());
+    let (mut uv_count, mut normal_count) = (0, 0);
+    for vertex in &mesh.vertices {
+        let p = &vertex.position;
+        match vertex.color {
+            // Vertex colors aren't part of the spec, but most tools read them like this
+            Some(color) => {
+                let c = color.to_vec3_f32s();
+                obj.push_str(&format!(
+                    "v {} {} {} {} {} {}\n",
+                    p.0, p.1, p.2, c.0, c.1, c.2
+                ));
+            }
+            None => obj.push_str(&format!("v {} {} {}\n", p.0, p.1, p.2)),
+        }
+        uv_indices.push(vertex.uv.map(|uv| {
+            obj.push_str(&format!("vt {} {}\n", uv.0, uv.1));
+            uv_count += 1;
+            uv_count
+        }));
+        normal_indices.push(vertex.normal.as_ref().map(|n| {
+            obj.push_str(&format!("vn {} {} {}\n", n.0, n.1, n.2));
+            normal_count += 1;
+            normal_count
+        }));
+    }
+
+    let materials = model.materials();
+    let default_material = Material {
+        texture: model.texture(),
+        ..Default::default()
+    };
+    let mut library = MaterialLibrary::new(name);
+    let mut current = None;
+    for t in &mesh.triangles {
+        let material = t
+            .material
+            .and_then(|i| materials.get(i))
+            .unwrap_or(&default_material);
+        let texture = material.texture.or(model.texture());
+        let textured =
+            texture.is_some() && t.indices.iter().all(|&i| uv_indices[i as usize].is_some());
+        // Textured surfaces ignore the triangle color, untextured ones need a material
+        // per color
+        let key = (
+            t.material,
+            (!textured).then_some([t.color.0, t.color.1, t.color.2]),
+        );
+        let index = library.get_or_insert(key, material, texture.filter(|_| textured), t.color);
+        if current != Some(index) {
+            obj.push_str(&format!("usemtl {}_{}\n", name, index));
+            current = Some(index);
+        }
+
+        obj.push('f');
+        let [i0, i1, i2] = t.indices;
+        let indices = if mirrored { [i0, i2, i1] } else { [i0, i1, i2] };
+        for i in indices {
+            let i = i as usize;
+            obj.push_str(&match (uv_indices[i], normal_indices[i]) {
+                (Some(vt), Some(vn)) => format!(" {}/{}/{}", i + 1, vt, vn),
+                (Some(vt), None) => format!(" {}/{}", i + 1, vt),
+                (None, Some(vn)) => format!(" {}//{}", i + 1, vn),
+                (None, None) => format!(" {}", i + 1),
+            });
+        }
+        obj.push('\n');
+    }
+
+    ObjExport {
+        obj,
+        mtl: library.mtl,
+        textures: library.textures,
+    }
+}
+
+/// The MTL materials written so far, one per model material and triangle color
+struct MaterialLibrary<'a, 'n> {
+    name: &'n str,
+    mtl: String,
+    indices: HashMap<(Option<usize>, Option<[u8; 3]>), usize>,
+    textures: Vec<(String, &'a Texture)>,
+}
+
+impl<'a, 'n> MaterialLibrary<'a, 'n> {
+    fn new(name: &'n str) -> Self {
+        Self {
+            name,
+            mtl: "# Exported from rasta\n".to_string(),
+            indices: HashMap::new(),
+            textures: vec![],
+        }
+    }
+
+    fn get_or_insert(
+        &mut self,
+        key: (Option<usize>, Option<[u8; 3]>),
+        material: &Material,
+        texture: Option<&'a Texture>,
+        color: Color,
+    ) -> usize {
+        if let Some(&index) = self.indices.get(&key) {
+            return index;
+        }
+        let index = self.indices.len();
+        self.indices.insert(key, index);
+
+        let diffuse = match texture {
+            Some(_) => material.diffuse.to_vec3_f32s(),
+            None => material
+                .diffuse
+                .to_vec3_f32s()
+                .component_wise_mul(&color.to_vec3_f32s()),
+        };
+        let (s, e) = (
+            material.specular.to_vec3_f32s(),
+            material.emissive.to_vec3_f32s(),
+        );
+        let a = material.ambient;
+        self.mtl.push_str(&format!(
+            "\nnewmtl {}_{}\nKa {} {} {}\nKd {} {} {}\nKs {} {} {}\nNs {}\nKe {} {} {}\n",
+            self.name,
+            index,
+            a,
+            a,
+            a,
+            diffuse.0,
+            diffuse.1,
+            diffuse.2,
+            s.0,
+            s.1,
+            s.2,
+            material.shininess,
+            e.0,
+            e.1,
+            e.2
+        ));
+        if material.opacity < 1.0 {
+            self.mtl.push_str(&format!("d {}\n", material.opacity));
+        }
+        if let Some(texture) = texture {
+            let file = match self
+                .textures
+                .iter()
+                .find(|(_, saved)| std::ptr::eq(*saved, texture))
+            {
+                Some((file, _)) => file.clone(),
+                None => {
+                    let file = format!("{}_{}.png", self.name, self.textures.len());
+                    self.textures.push((file.clone(), texture));
+                    file
+                }
+            };
+            self.mtl.push_str(&format!("map_Kd {}\n", file));
+        }
+        index
+    }
+}
+
+/// `mtllib` and `usemtl` end at the first whitespace, so names can't contain any
+fn file_name(name: &str) -> String {
+    name.replace(char::is_whitespace, "_")
+}
+
+fn to_string<T: ToString>(t: T) -> String {
+    t.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        math::Vec3,
+        wavefront::{WavefrontMaterial, WavefrontObj},
+    };
+
+    #[test]
+    fn round_trip() {
+        let quad = Mesh::from_triangles(&[
+            Triangle::new(
+                Vec3(0.0, 0.0, 0.0),
+                Vec3(1.0, 0.0, 0.0),
+                Vec3(1.0, 1.0, 0.0),
+                Color::RED,
+                None,
+            ),
+            Triangle::new(
+                Vec3(0.0, 0.0, 0.0),
+                Vec3(1.0, 1.0, 0.0),
+                Vec3(0.0, 1.0, 0.0),
+                Color::BLUE,
+                None,
+            ),
+        ]);
+        let export = to_obj(&quad, &Mat4::translate(Vec3(0.0, 0.0, -2.0)), "quad");
+
+        assert_eq!(
+            export.obj.lines().filter(|l| l.starts_with("v ")).count(),
+            4
+        );
+        assert!(export.obj.contains("usemtl quad_1\nf 1 3 4\n"));
+
+        let obj = WavefrontObj::from_reader(export.obj.as_bytes(), 1.0).unwrap();
+        let triangles = obj.make_triangles(None, false, false, false).unwrap();
+        assert_eq!(triangles[1].p2.2, -2.0);
+
+        let materials =
+            WavefrontMaterial::parse_library(export.mtl.as_bytes(), Path::new("")).unwrap();
+        assert_eq!(materials[1].name, "quad_1");
+        assert_eq!(materials[1].diffuse_color(), Color::BLUE);
+
+        let mirrored = to_obj(&quad, &Mat4::scale(Vec3(-1.0, 1.0, 1.0)), "quad");
+        assert!(mirrored.obj.contains("usemtl quad_1\nf 1 4 3\n"));
+
+        let spaced = to_obj(&quad, &Mat4::identity(), "my quad");
+        assert!(spaced.obj.contains("mtllib my_quad.mtl\n"));
+        assert!(spaced.mtl.contains("newmtl my_quad_0\n"));
+    }
+}
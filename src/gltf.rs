@@ -0,0 +1,317 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ::gltf::{
+    buffer, image,
+    material::AlphaMode,
+    mesh::Mode,
+    texture::{MagFilter, WrappingMode},
+    Document, Gltf, Node,
+};
+
+use crate::{
+    material::{BlendMode, Material},
+    math::{Mat4, Vec2, Vec3, Vec4},
+    mesh::{Mesh, MeshTriangle, Vertex},
+    normals::DEFAULT_CREASE_ANGLE,
+    rasterize::Color,
+    texture::{FilterMode, Texture, WrapMode},
+};
+
+/// A glTF 2.0 asset, either a .gltf file with its buffers (external files or data uris)
+/// or a binary .glb file
+pub struct GltfScene {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+    dir: PathBuf,
+    scale: f32,
+}
+
+impl GltfScene {
+    pub fn from_file<P: AsRef<Path>>(path: P, scale: f32) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_slice(&bytes, dir, scale)
+    }
+
+    /// Parse a .gltf or .glb file from memory, relative uris are resolved against `dir`
+    pub fn from_slice(bytes: &[u8], dir: &Path, scale: f32) -> Result<Self, String> {
+        let Gltf { document, mut blob } = Gltf::from_slice(bytes).map_err(to_string)?;
+        let buffers = document
+            .buffers()
+            .map(|b| match b.source() {
+                buffer::Source::Bin => blob
+                    .take()
+                    .ok_or_else(|| "glb file without a binary chunk".to_string()),
+                buffer::Source::Uri(uri) => read_uri(dir, uri),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            document,
+            buffers,
+            dir: dir.to_path_buf(),
+            scale,
+        })
+    }
+
+    /// Decode the images of the asset's textures, in the order of the glTF textures
+    pub fn load_textures(&self) -> Result<Vec<Texture>, String> {
+        self.document
+            .textures()
+            .map(|texture| {
+                let bytes = match texture.source().source() {
+                    image::Source::View { view, .. } => {
+                        let buffer = &self.buffers[view.buffer().index()];
+                        buffer[view.offset()..view.offset() + view.length()].to_vec()
+                    }
+                    image::Source::Uri { uri, .. } => read_uri(&self.dir, uri)?,
+                };
+                let format = ::image::guess_format(&bytes).map_err(to_string)?;
+                let sampler = texture.sampler();
+                let filter = match sampler.mag_filter() {
+                    Some(MagFilter::Nearest) => FilterMode::Nearest,
+                    _ => FilterMode::Bilinear,
+                };
+                Ok(Texture::from_bytes(&bytes, format)?
+                    .with_filter(filter)
+                    .with_wrap(wrap_mode(sampler.wrap_s()), wrap_mode(sampler.wrap_t())))
+            })
+            .collect()
+    }
+
+    /// All the triangles of the default scene (or the first one) as a single mesh, with
+    /// the node transforms applied. `textures` are the ones from [`Self::load_textures`].
+    pub fn mesh<'a>(&self, textures: &'a [Texture]) -> Result<Mesh<'a>, String> {
+        let materials = self
+            .document
+            .materials()
+            .map(|m| {
+                let pbr = m.pbr_metallic_roughness();
+                let [r, g, b, a] = pbr.base_color_factor();
+                let [er, eg, eb] = m.emissive_factor();
+                // There is no metallic/roughness model, rough surfaces just get dimmer and
+                // wider highlights
+                let gloss = 1.0 - pbr.roughness_factor();
+                Material {
+                    diffuse: Color::from_vec3_f32s(Vec3(r, g, b)),
+                    texture: pbr
+                        .base_color_texture()
+                        .and_then(|info| textures.get(info.texture().index())),
+                    specular: Color::from_vec3_f32s(Vec3(gloss, gloss, gloss)),
+                    shininess: 2.0 + 126.0 * gloss * gloss,
+                    emissive: Color::from_vec3_f32s(Vec3(er, eg, eb)),
+                    ambient: 1.0,
+                    alpha_cutoff: match m.alpha_mode() {
+                        AlphaMode::Mask => m.alpha_cutoff().unwrap_or(0.5),
+                        AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+                    },
+                    blend: match m.alpha_mode() {
+                        AlphaMode::Blend => BlendMode::Alpha,
+                        AlphaMode::Opaque | AlphaMode::Mask => BlendMode::Opaque,
+                    },
+                    opacity: a,
+                }
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(vec![], vec![]).with_materials(materials);
+        let scene = self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next())
+            .ok_or_else(|| "glTF file without a scene".to_string())?;
+        let root = Mat4::scale(Vec3(self.scale, self.scale, self.scale));
+        for node in scene.nodes() {
+            self.add_node(&mut mesh, &node, &root)?;
+        }
+        Ok(mesh)
+    }
+
+    fn add_node(&self, mesh: &mut Mesh, node: &Node, parent: &Mat4<f32>) -> Result<(), String> {
+        let m = node.transform().matrix();
+        // glTF matrices are column major
+        let local = Mat4::new(
+            m[0][0], m[1][0], m[2][0], m[3][0], m[0][1], m[1][1], m[2][1], m[3][1], m[0][2],
+            m[1][2], m[2][2], m[3][2], m[0][3], m[1][3], m[2][3], m[3][3],
+        );
+        let transform = parent * local;
+
+        if let Some(node_mesh) = node.mesh() {
+            let normal_matrix = transform
+                .invert()
+                .map(|inverse| inverse.transpose())
+                .unwrap_or_else(|| transform.clone());
+            // Mirroring transforms flip the winding
+            let mirrored = transform.det() < 0.0;
+
+            for primitive in node_mesh.primitives() {
+                if primitive.mode() != Mode::Triangles {
+                    log::warn!(
+                        "Skipping {:?} primitive of mesh {}, only triangles are supported",
+                        primitive.mode(),
+                        node_mesh.index()
+                    );
+                    continue;
+                }
+                let reader = primitive.reader(|b| Some(&self.buffers[b.index()]));
+                let positions = reader
+                    .read_positions()
+                    .ok_or_else(|| format!("Mesh {} has no positions", node_mesh.index()))?;
+                let mut normals = reader.read_normals();
+                // The set of texture coordinates the base color texture uses
+                let pbr = primitive.material().pbr_metallic_roughness();
+                let tex_coord = pbr.base_color_texture().map_or(0, |info| info.tex_coord());
+                let mut uvs = reader.read_tex_coords(tex_coord).map(|uvs| uvs.into_f32());
+                let mut colors = reader.read_colors(0).map(|colors| colors.into_rgb_f32());
+
+                // The primitive's own mesh, merged into `mesh` once its normals are known
+                let mut part = Mesh::new(vec![], vec![]);
+                for [x, y, z] in positions {
+                    let position = (&transform * Vec4(x, y, z, 1.0)).drop_fourth_component();
+                    let normal = normals.as_mut().and_then(Iterator::next).map(|[x, y, z]| {
+                        (&normal_matrix * Vec4(x, y, z, 0.0))
+                            .drop_fourth_component()
+                            .normalize()
+                    });
+                    let uv = uvs
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .map(|[u, v]| Vec2(u, v));
+                    let color = colors
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .map(|[r, g, b]| Color::from_vec3_f32s(Vec3(r, g, b)));
+                    part.vertices.push(Vertex {
+                        position,
+                        normal,
+                        uv,
+                        color,
+                    });
+                }
+                let count = part.vertices.len() as u32;
+
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..count).collect(),
+                };
+                if let Some(index) = indices.iter().find(|&&i| i >= count) {
+                    return Err(format!(
+                        "Index {} is out of range in mesh {}",
+                        index,
+                        node_mesh.index()
+                    ));
+                }
+                for corners in indices.chunks_exact(3) {
+                    let (a, b, c) = (corners[0], corners[1], corners[2]);
+                    part.triangles.push(MeshTriangle {
+                        indices: if mirrored { [a, c, b] } else { [a, b, c] },
+                        color: Color::WHITE,
+                        material: primitive.material().index(),
+                    });
+                }
+                // Like obj files without normals, smooth the faces up to the crease angle
+                if normals.is_none() {
+                    part.generate_normals(DEFAULT_CREASE_ANGLE);
+                }
+
+                let first = mesh.vertices.len() as u32;
+                mesh.vertices.extend(part.vertices);
+                mesh.triangles
+                    .extend(part.triangles.into_iter().map(|t| MeshTriangle {
+                        indices: t.indices.map(|i| first + i),
+                        ..t
+                    }));
+            }
+        }
+
+        for child in node.children() {
+            self.add_node(mesh, &child, &transform)?;
+        }
+        Ok(())
+    }
+}
+
+/// Contents of a buffer or image uri, either a base64 data uri or a path relative to `dir`
+fn read_uri(dir: &Path, uri: &str) -> Result<Vec<u8>, String> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, data) = data
+            .split_once(";base64,")
+            .ok_or_else(|| format!("Unsupported data uri: {}", uri))?;
+        return base64::decode(data).map_err(to_string);
+    }
+    let path = dir.join(uri);
+    fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn wrap_mode(mode: WrappingMode) -> WrapMode {
+    match mode {
+        WrappingMode::Repeat => WrapMode::Repeat,
+        WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+        WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+    }
+}
+
+fn to_string<T: ToString>(t: T) -> String {
+    t.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::Model;
+
+    #[test]
+    fn triangle_with_node_transform() {
+        // Positions followed by the vertex colors
+        let buffer: Vec<u8> = [
+            0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+            1.0,
+        ]
+        .iter()
+        .flat_map(|f| f.to_le_bytes())
+        .collect();
+        let gltf = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [{{ "mesh": 0, "translation": [0.0, 0.0, -2.0] }}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0, "COLOR_0": 1 }}, "material": 0 }}] }}],
+                "materials": [{{ "pbrMetallicRoughness": {{ "baseColorFactor": [1.0, 0.0, 0.0, 1.0] }} }}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]
+                }}, {{
+                    "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3"
+                }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": 36 }}
+                ],
+                "buffers": [{{
+                    "byteLength": 72,
+                    "uri": "data:application/octet-stream;base64,{}"
+                }}]
+            }}"#,
+            base64::encode(buffer)
+        );
+
+        let scene = GltfScene::from_slice(gltf.as_bytes(), Path::new(""), 2.0).unwrap();
+        let mesh = scene.mesh(&[]).unwrap();
+
+        assert_eq!(mesh.triangles.len(), 1);
+        let t = mesh.triangles().next().unwrap();
+        assert_eq!((t.p1.0, t.p1.1, t.p1.2), (2.0, 0.0, -4.0));
+        assert_eq!(t.material, Some(0));
+        assert_eq!(mesh.materials[0].diffuse, Color::RED);
+        assert_eq!(t.colors, Some([Color::RED, Color::GREEN, Color::BLUE]));
+        // There are no normals in the file, the generated ones face the front
+        for n in t.normals.unwrap() {
+            assert_eq!((n.0, n.1, n.2), (0.0, 0.0, 1.0));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{io::Cursor, path::Path};
+
+use image::{
+    codecs::pnm::{PnmSubtype, SampleEncoding},
+    ImageFormat, ImageOutputFormat, RgbImage,
+};
+
+use crate::{
+    canvas::{canvas_coords_to_screen_coords, Canvas, IntoPixelValue},
+    rasterize::Color,
+};
+
+/// Headless canvas that renders into an in-memory RGB buffer.
+///
+/// Useful for rendering without a window or a browser, e.g. on CI or to produce
+/// thumbnails. The buffer is laid out row by row, top to bottom, 3 bytes per pixel.
+#[derive(Debug, Clone)]
+pub struct ImageCanvas {
+    width: u32,
+    height: u32,
+    buffer: Vec<u8>,
+}
+
+impl ImageCanvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        let buffer = vec![0; width as usize * height as usize * 3];
+
+        Self {
+            width,
+            height,
+            buffer,
+        }
+    }
+
+    /// Raw RGB bytes of the canvas
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Raw RGBA bytes of the canvas, alpha is always 255
+    pub fn to_rgba_bytes(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for pixel in self.buffer.chunks_exact(3) {
+            rgba.extend_from_slice(pixel);
+            rgba.push(255);
+        }
+        rgba
+    }
+
+    /// Color of the pixel at screen coordinates (origin top left)
+    pub fn pixel(&self, x: u32, y: u32) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = (y as usize * self.width as usize * 3) + (x as usize * 3);
+        Some(Color(
+            self.buffer[i],
+            self.buffer[i + 1],
+            self.buffer[i + 2],
+        ))
+    }
+
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_raw(self.width, self.height, self.buffer.clone())
+            .expect("Buffer size always matches the canvas dimensions")
+    }
+
+    /// Save the canvas, picking the image format from the file extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("raw") | Some("rgb") => self.save_raw(path),
+            Some("ppm") => self.save_ppm(path),
+            _ => self.to_image().save(path).map_err(to_string),
+        }
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        self.to_image()
+            .save_with_format(path, ImageFormat::Png)
+            .map_err(to_string)
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        std::fs::write(path, self.encode_ppm()?).map_err(to_string)
+    }
+
+    /// Write the raw RGB bytes without any header
+    pub fn save_raw<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        std::fs::write(path, &self.buffer).map_err(to_string)
+    }
+
+    pub fn encode_png(&self) -> Result<Vec<u8>, String> {
+        self.encode(ImageOutputFormat::Png)
+    }
+
+    pub fn encode_ppm(&self) -> Result<Vec<u8>, String> {
+        self.encode(ImageOutputFormat::Pnm(PnmSubtype::Pixmap(
+            SampleEncoding::Binary,
+        )))
+    }
+
+    fn encode(&self, format: ImageOutputFormat) -> Result<Vec<u8>, String> {
+        let mut bytes = Cursor::new(vec![]);
+        self.to_image()
+            .write_to(&mut bytes, format)
+            .map_err(to_string)?;
+        Ok(bytes.into_inner())
+    }
+}
+
+impl Canvas for ImageCanvas {
+    fn put_pixel<X: IntoPixelValue, Y: IntoPixelValue>(&mut self, x: X, y: Y, color: Color) {
+        if let Some((x, y)) = canvas_coords_to_screen_coords(x, y, self.width, self.height) {
+            let i: usize = (y as usize * self.width as usize * 3) + (x as usize * 3);
+
+            self.buffer[i] = color.0;
+            self.buffer[i + 1] = color.1;
+            self.buffer[i + 2] = color.2;
+        }
+    }
+
+    fn get_pixel<X: IntoPixelValue, Y: IntoPixelValue>(&self, x: X, y: Y) -> Option<Color> {
+        let (x, y) = canvas_coords_to_screen_coords(x, y, self.width, self.height)?;
+        self.pixel(x, y)
+    }
+
+    fn clear(&mut self, color: Color) {
+        for pixel in self.buffer.chunks_exact_mut(3) {
+            pixel[0] = color.0;
+            pixel[1] = color.1;
+            pixel[2] = color.2;
+        }
+    }
+
+    /// Nothing to present, the buffer is the final image
+    fn draw(&mut self) {}
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+fn to_string<T: ToString>(t: T) -> String {
+    t.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn put_pixel_uses_canvas_coords() {
+        let mut canvas = ImageCanvas::new(4, 4);
+        canvas.clear(Color(1, 2, 3));
+        canvas.put_pixel(0, 0, Color::RED);
+        canvas.put_pixel(-2, 1, Color::GREEN);
+        // Out of bounds pixels are ignored
+        canvas.put_pixel(10, 10, Color::BLUE);
+
+        assert_eq!(canvas.pixel(2, 1), Some(Color::RED));
+        assert_eq!(canvas.pixel(0, 0), Some(Color::GREEN));
+        assert_eq!(canvas.pixel(3, 3), Some(Color(1, 2, 3)));
+        assert_eq!(canvas.pixel(4, 0), None);
+    }
+
+    #[test]
+    fn encode_ppm() {
+        let mut canvas = ImageCanvas::new(2, 1);
+        canvas.clear(Color::BLUE);
+
+        let ppm = canvas.encode_ppm().unwrap();
+        assert_eq!(&ppm[..2], b"P6");
+        assert_eq!(&ppm[ppm.len() - 6..], &[0, 0, 255, 0, 0, 255]);
+        assert_eq!(canvas.to_rgba_bytes(), vec![0, 0, 255, 255, 0, 0, 255, 255]);
+    }
+}
@@ -1,11 +1,22 @@
+pub mod cache;
 pub mod canvas;
+pub mod clip;
 pub mod draw;
+pub mod export;
+pub mod gltf;
+pub mod image_canvas;
 pub mod lerp;
 pub mod light;
+pub mod material;
 pub mod math;
+pub mod mesh;
+pub mod normals;
 pub mod object;
+pub mod ply;
 pub mod rasterize;
+pub mod stl;
 pub mod texture;
+pub mod triangulate;
 pub mod wavefront;
 
 #[cfg(not(target_arch = "wasm32"))]
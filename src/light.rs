@@ -1,9 +1,163 @@
-use crate::math::Vec3;
+use std::ops::Add;
 
+use crate::{
+    math::{Mat4, Radians, Vec3, Vec4},
+    rasterize::Color,
+};
+
+/// Lights are given in world space, the rasterizer moves them into view space every
+/// frame so they can be animated with [`crate::draw::Rasterizer::lights_mut`].
+#[derive(Debug, Clone)]
 pub enum Light {
-    Ambient(f32),
-    Directional(f32, Vec3<f32>),
-    Point(f32, Vec3<f32>),
+    /// Intensity and color
+    Ambient(f32, Color),
+    /// Intensity, color and the direction *towards* the light
+    Directional(f32, Color, Vec3<f32>),
+    /// Intensity, color, position and how the intensity falls off with distance
+    Point(f32, Color, Vec3<f32>, Attenuation),
+    /// Cone of light, like a flashlight. Full intensity inside `inner_angle` fading out
+    /// to nothing at `outer_angle`, both measured from `direction`.
+    Spot {
+        intensity: f32,
+        color: Color,
+        position: Vec3<f32>,
+        /// The direction the light is pointing *at*
+        direction: Vec3<f32>,
+        inner_angle: Radians,
+        outer_angle: Radians,
+        attenuation: Attenuation,
+    },
+}
+
+impl Light {
+    pub fn transform(&self, m: &Mat4<f32>) -> Light {
+        match self {
+            Light::Ambient(intensity, color) => Light::Ambient(*intensity, *color),
+            Light::Directional(intensity, color, dir) => Light::Directional(
+                *intensity,
+                *color,
+                (m * Vec4(dir.0, dir.1, dir.2, 0.0)).drop_fourth_component(),
+            ),
+            Light::Point(intensity, color, pos, attenuation) => Light::Point(
+                *intensity,
+                *color,
+                (m * pos.to_point_vec4()).drop_fourth_component(),
+                *attenuation,
+            ),
+            Light::Spot {
+                intensity,
+                color,
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => Light::Spot {
+                intensity: *intensity,
+                color: *color,
+                position: (m * position.to_point_vec4()).drop_fourth_component(),
+                direction: (m * direction.to_direction_vec4()).drop_fourth_component(),
+                inner_angle: *inner_angle,
+                outer_angle: *outer_angle,
+                attenuation: *attenuation,
+            },
+        }
+    }
+
+    /// Color of the light scaled by its intensity
+    pub fn radiance(&self) -> Vec3<f32> {
+        let (intensity, color) = match self {
+            Light::Ambient(intensity, color)
+            | Light::Directional(intensity, color, _)
+            | Light::Point(intensity, color, _, _)
+            | Light::Spot {
+                intensity, color, ..
+            } => (intensity, color),
+        };
+        color.to_vec3_f32s() * *intensity
+    }
+
+    /// How much of a spot light reaches a point, given the vector from the point to the
+    /// light. Always 1 for the other kinds of light.
+    pub fn cone_factor(&self, to_light: &Vec3<f32>) -> f32 {
+        match self {
+            Light::Spot {
+                direction,
+                inner_angle,
+                outer_angle,
+                ..
+            } => {
+                let cos_theta = (to_light * -1.0).angle(direction);
+                let cos_inner = inner_angle.value().cos();
+                let cos_outer = outer_angle.value().cos();
+                if cos_inner <= cos_outer {
+                    // No soft edge
+                    return if cos_theta >= cos_outer { 1.0 } else { 0.0 };
+                }
+                ((cos_theta - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0)
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+/// Distance falloff of a light, the intensity is scaled by
+/// `1 / (constant + linear * d + quadratic * d^2)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub const NONE: Self = Attenuation::new(1.0, 0.0, 0.0);
+
+    pub const fn new(constant: f32, linear: f32, quadratic: f32) -> Self {
+        Self {
+            constant,
+            linear,
+            quadratic,
+        }
+    }
+
+    pub fn factor(&self, distance: f32) -> f32 {
+        1.0 / (self.constant + self.linear * distance + self.quadratic * distance * distance)
+    }
+}
+
+impl Default for Attenuation {
+    /// Falls off to almost nothing over ~50 units
+    fn default() -> Self {
+        Self::new(1.0, 0.09, 0.032)
+    }
+}
+
+/// Light reaching a point on a surface, split into the part that is scattered (tinted by
+/// the surface color) and the part that is reflected as highlights (tinted by the
+/// material's specular color)
+#[derive(Debug, Clone)]
+pub struct Illumination {
+    pub diffuse: Vec3<f32>,
+    pub specular: Vec3<f32>,
+}
+
+impl Illumination {
+    pub const NONE: Self = Illumination {
+        diffuse: Vec3(0.0, 0.0, 0.0),
+        specular: Vec3(0.0, 0.0, 0.0),
+    };
+}
+
+impl Add for Illumination {
+    type Output = Illumination;
+
+    fn add(self, rhs: Illumination) -> Self::Output {
+        Illumination {
+            diffuse: self.diffuse + rhs.diffuse,
+            specular: self.specular + rhs.specular,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -17,3 +171,70 @@ impl Default for Shading {
         Self::Gourad
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::Degrees;
+
+    #[test]
+    fn attenuation() {
+        for distance in [0.0, 1.0, 10.0, 1000.0] {
+            assert_eq!(Attenuation::NONE.factor(distance), 1.0);
+        }
+
+        let default = Attenuation::default();
+        assert_eq!(default, Attenuation::new(1.0, 0.09, 0.032));
+        assert_eq!(default.factor(0.0), 1.0);
+        // 1 / (1 + 0.09 * 10 + 0.032 * 100)
+        assert!((default.factor(10.0) - 1.0 / 5.1).abs() < 1e-6);
+        assert!(default.factor(50.0) < 0.02);
+    }
+
+    #[test]
+    fn illumination_adds_per_channel() {
+        let a = Illumination {
+            diffuse: Vec3(1.0, 0.0, 0.5),
+            specular: Vec3(0.0, 0.25, 0.0),
+        };
+        let b = Illumination {
+            diffuse: Vec3(0.0, 0.5, 0.5),
+            specular: Vec3(0.5, 0.25, 1.0),
+        };
+        let sum = a + b;
+        let (d, s) = (sum.diffuse, sum.specular);
+        assert_eq!((d.0, d.1, d.2), (1.0, 0.5, 1.0));
+        assert_eq!((s.0, s.1, s.2), (0.5, 0.5, 1.0));
+    }
+
+    #[test]
+    fn spot_cone() {
+        let spot = Light::Spot {
+            intensity: 1.0,
+            color: Color::WHITE,
+            position: Vec3(0.0, 0.0, 0.0),
+            direction: Vec3(0.0, -1.0, 0.0),
+            inner_angle: Degrees(10.0).into(),
+            outer_angle: Degrees(20.0).into(),
+            attenuation: Attenuation::NONE,
+        };
+        // From a point below the light, at `degrees` from the direction it points at
+        let factor = |degrees: f32| {
+            let angle = degrees.to_radians();
+            spot.cone_factor(&Vec3(-angle.sin(), angle.cos(), 0.0))
+        };
+
+        assert_eq!(factor(0.0), 1.0);
+        assert_eq!(factor(9.0), 1.0);
+        assert_eq!(factor(21.0), 0.0);
+        assert_eq!(factor(90.0), 0.0);
+        let (a, b, c) = (factor(12.0), factor(15.0), factor(18.0));
+        assert!(1.0 > a && a > b && b > c && c > 0.0);
+        // Roughly half way, the falloff is linear in the cosine of the angle
+        assert!((b - 0.5).abs() < 0.1);
+
+        // Other lights aren't limited to a cone
+        let point = Light::Point(1.0, Color::WHITE, Vec3(0.0, 0.0, 0.0), Attenuation::NONE);
+        assert_eq!(point.cone_factor(&Vec3(1.0, 0.0, 0.0)), 1.0);
+    }
+}
@@ -1,17 +1,19 @@
 use core::time;
+use std::collections::HashMap;
 
 use image::{open, GenericImageView};
 use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
 
 use crate::{
     canvas::Canvas,
-    draw::Rasterizer,
+    draw::{Rasterizer, TransparentQueue, Viewport},
     light::{Light, Shading},
+    material::{BlendMode, Material},
     math::{Degrees, Mat4, Vec3},
     object::{Cube, Instance, Model, Triangle, WavefrontModel},
     rasterize::{Color, Point},
     sdl_canvas::SDLCanvas,
-    texture::Texture,
+    texture::{FilterMode, Texture},
     wavefront,
 };
 
@@ -53,17 +55,28 @@ pub fn main() -> Result<(), String> {
         .map_err(to_string)?;
 
     // let shrek_texture = Texture::load("./shrek.png")?;
-    let shrek_texture = Texture::load("./assets/textures/diamond_ore.png")?;
+    let shrek_texture =
+        Texture::load("./assets/textures/diamond_ore.png")?.with_filter(FilterMode::Nearest);
     let rust_texture = Texture::load("./assets/textures/rust-texture.png")?;
     let helmet_texture = Texture::load("./assets/textures/helmet.jpeg")?;
 
     let mut sdl_canvas = SDLCanvas::new(WIDTH, HEIGHT, canvas, texture);
 
     // let obj = wavefront::WavefrontObj::from_file("./assets/models/homer.obj", 1.0);
-    let obj = wavefront::WavefrontObj::from_file("./assets/models/helmet.obj", 1.0);
-
     // let helmet_model = WavefrontModel::new(obj, Color(200, 200, 0), false);
-    let helmet_model = WavefrontModel::new_with_tex(obj, &helmet_texture, true);
+
+    // The cache is made with `cargo run --bin rasta-cache assets/models/helmet.obj`, parse
+    // the obj when it's missing
+    let no_textures = HashMap::new();
+    let helmet_model = match std::fs::read("./assets/models/helmet.rmesh") {
+        Ok(bytes) => {
+            WavefrontModel::from_cache(&bytes, &no_textures)?.with_texture(&helmet_texture)
+        }
+        Err(_) => {
+            let obj = wavefront::WavefrontObj::from_file("./assets/models/helmet.obj", 1.0)?;
+            WavefrontModel::new_with_tex(obj, &helmet_texture, true)?
+        }
+    };
 
     let cube = Cube::new_with_texture(
         (-0.5, 0.5, 0.5).into(),
@@ -95,7 +108,8 @@ pub fn main() -> Result<(), String> {
             [(1.0, 1.0), (1.0, 0.0), (0.0, 0.0)],
         ],
         &rust_texture,
-    );
+    )
+    .with_material(Material::default().specular(Color::BLACK, 0.0));
 
     let textured_cube = Cube::new_with_texture(
         (-0.5, 0.5, 0.5).into(),
@@ -145,6 +159,12 @@ pub fn main() -> Result<(), String> {
             .pos((-4.0, -1.0, -5.0).into())
             .shading(Shading::Phong)
             .build(),
+        Instance::new(&cube)
+            .pos((1.5, -0.5, -2.0).into())
+            .shading(Shading::Phong)
+            .blend(BlendMode::Alpha)
+            .opacity(0.5)
+            .build(),
     ];
 
     let aspect = sdl_canvas.height() as f32 / sdl_canvas.width() as f32;
@@ -159,17 +179,21 @@ pub fn main() -> Result<(), String> {
     );
     let view_matrix = camera_translation.invert().unwrap() * camera_rotation.invert().unwrap();
     let projection = viewport_to_canvas * perspective;
+    let viewport = Viewport {
+        cw: sdl_canvas.width() as f32,
+        ch: sdl_canvas.height() as f32,
+        vw: 1.0,
+        vh: aspect,
+        d: 1.0,
+        far: 1000.0,
+    };
     let mut raster = Rasterizer::new(
-        sdl_canvas.width() as f32,
-        sdl_canvas.height() as f32,
-        1.0,
-        aspect,
-        1.0,
+        viewport,
         view_matrix,
         projection,
         vec![
-            Light::Ambient(0.2),
-            Light::Directional(0.4, Vec3(0.0, 0.0, 1.0)),
+            Light::Ambient(0.2, Color::WHITE),
+            Light::Directional(0.8, Color::WHITE, Vec3(-1.0, 1.0, 1.0)),
         ],
     );
 
@@ -195,6 +219,7 @@ pub fn main() -> Result<(), String> {
 
         if !paused {
             raster.clear(&mut sdl_canvas, Color(21, 20, 28));
+            let mut transparent = TransparentQueue::new();
 
             for (c, i) in instances.iter_mut().enumerate() {
                 let s = if c % 2 == 0 { -1.0 } else { 1.0 };
@@ -202,17 +227,19 @@ pub fn main() -> Result<(), String> {
                 let delta = (t as f32 / 20.0).sin() * 0.02;
                 i.set_pos(i.pos() + Vec3(0.0, delta, -delta + delta));
                 i.update_transform_matrix();
-                raster.render_instance(&mut sdl_canvas, i, i.model.texture());
+                raster.queue_instance(&mut sdl_canvas, i, i.model.texture(), &mut transparent);
             }
             truck_instance.set_rotation(Degrees((t as f32 / 30.0) * 20.0));
             let delta = (t as f32 / 20.0).sin() * 0.05;
             // truck_instance.set_pos(truck_instance.pos() + Vec3(0.0, delta, -delta));
             truck_instance.update_transform_matrix();
-            raster.render_instance(
+            raster.queue_instance(
                 &mut sdl_canvas,
                 &truck_instance,
                 truck_instance.model.texture(),
+                &mut transparent,
             );
+            raster.render_transparent(&mut sdl_canvas, &mut transparent);
 
             sdl_canvas.draw();
             t += 1;
@@ -1,17 +1,16 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use wasm_bindgen::{prelude::*, JsCast};
 
 use crate::{
     canvas::Canvas,
-    draw::Rasterizer,
+    draw::{Rasterizer, TransparentQueue, Viewport},
     light::{Light, Shading},
     math::{Degrees, Mat4, Vec3},
     object::{Cube, Instance, Model, WavefrontModel},
     rasterize::Color,
-    texture::Texture,
+    texture::{FilterMode, Texture},
     wasm_canvas::WasmCanvas,
-    wavefront,
 };
 
 fn request_animation_frame(f: &Closure<dyn FnMut()>) {
@@ -48,7 +47,9 @@ pub fn start() {
         Texture::from_bytes(rust, image::ImageFormat::Png).unwrap(),
     ));
     let dia_texture: &Texture = Box::leak(Box::new(
-        Texture::from_bytes(dia, image::ImageFormat::Png).unwrap(),
+        Texture::from_bytes(dia, image::ImageFormat::Png)
+            .unwrap()
+            .with_filter(FilterMode::Nearest),
     ));
     let helmet_texture: &Texture = Box::leak(Box::new(
         Texture::from_bytes(
@@ -58,15 +59,16 @@ pub fn start() {
         .unwrap(),
     ));
 
-    let helmet_model = include_bytes!("../assets/models/helmet.obj");
-    let obj = wavefront::WavefrontObj::from_reader(helmet_model.as_ref(), 1.0);
+    // Made from helmet.obj with the rasta-cache binary, a fraction of the size of the obj
+    let helmet_model = include_bytes!("../assets/models/helmet.rmesh");
+    let no_textures: &HashMap<_, _> = Box::leak(Box::new(HashMap::new()));
 
     // let helmet_model = WavefrontModel::new(obj, Color(200, 200, 0), false);
-    let helmet_model = Box::leak(Box::new(WavefrontModel::new_with_tex(
-        obj,
-        &helmet_texture,
-        true,
-    )));
+    let helmet_model = Box::leak(Box::new(
+        WavefrontModel::from_cache(helmet_model, no_textures)
+            .unwrap()
+            .with_texture(helmet_texture),
+    ));
 
     let helmet_instance = Box::leak(Box::new(
         Instance::new(helmet_model)
@@ -91,17 +93,21 @@ pub fn start() {
     );
     let view_matrix = camera_translation.invert().unwrap() * camera_rotation.invert().unwrap();
     let projection = viewport_to_canvas * perspective;
+    let viewport = Viewport {
+        cw: wasm_canvas.width() as f32,
+        ch: wasm_canvas.height() as f32,
+        vw: 1.0,
+        vh: aspect,
+        d: 1.0,
+        far: 1000.0,
+    };
     let raster = Rc::new(RefCell::new(Rasterizer::new(
-        wasm_canvas.width() as f32,
-        wasm_canvas.height() as f32,
-        1.0,
-        aspect,
-        1.0,
+        viewport,
         view_matrix,
         projection,
         vec![
-            Light::Ambient(0.2),
-            Light::Directional(0.4, Vec3(0.0, 0.0, 1.0)),
+            Light::Ambient(0.2, Color::WHITE),
+            Light::Directional(0.8, Color::WHITE, Vec3(-1.0, 1.0, 1.0)),
         ],
     )));
 
@@ -183,6 +189,7 @@ pub fn start() {
     *raf.borrow_mut() = Some(Closure::wrap(Box::new(move || {
         let mut raster = raster.borrow_mut();
         raster.clear(&mut wasm_canvas, Color(21, 20, 28));
+        let mut transparent = TransparentQueue::new();
 
         let t = *t_cell.borrow();
 
@@ -192,16 +199,18 @@ pub fn start() {
             let delta = (t as f32 / 120.0).sin() * 0.0045;
             i.set_pos(i.pos() + Vec3(0.0, delta, -delta + delta));
             i.update_transform_matrix();
-            raster.render_instance(&mut wasm_canvas, i, i.model.texture());
+            raster.queue_instance(&mut wasm_canvas, i, i.model.texture(), &mut transparent);
         }
 
         helmet_instance.set_rotation(Degrees((t as f32 / 30.0) * 13.0));
         helmet_instance.update_transform_matrix();
-        raster.render_instance(
+        raster.queue_instance(
             &mut wasm_canvas,
             &helmet_instance,
             helmet_instance.model.texture(),
+            &mut transparent,
         );
+        raster.render_transparent(&mut wasm_canvas, &mut transparent);
 
         wasm_canvas.draw();
         *t_cell.borrow_mut() += 1;
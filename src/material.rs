@@ -0,0 +1,183 @@
+use crate::{light::Illumination, math::Vec3, rasterize::Color, texture::Texture};
+
+/// How a surface is combined with what's already been drawn behind it. Anything but
+/// `Opaque` is transparent: it doesn't write to the depth buffer, and should be drawn
+/// after the opaque surfaces, see [`crate::draw::TransparentQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Replaces what's behind
+    #[default]
+    Opaque,
+    /// Mixed with what's behind by its alpha, like glass
+    Alpha,
+    /// Added to what's behind, for glows and fire
+    Additive,
+    /// Darkens what's behind by its color, like tinted glass or shadow decals
+    Multiply,
+}
+
+impl BlendMode {
+    /// Combine a surface of color `source` and opacity `alpha` with the `destination`
+    /// color behind it
+    pub fn blend(self, source: Color, alpha: f32, destination: Color) -> Color {
+        let (s, d) = (source.to_vec3_f32s(), destination.to_vec3_f32s());
+        let alpha = alpha.clamp(0.0, 1.0);
+        Color::from_vec3_f32s(match self {
+            BlendMode::Opaque => s,
+            BlendMode::Alpha => s * alpha + d * (1.0 - alpha),
+            BlendMode::Additive => d + s * alpha,
+            BlendMode::Multiply => {
+                let tint = s * alpha + Vec3(1.0 - alpha, 1.0 - alpha, 1.0 - alpha);
+                d.component_wise_mul(&tint)
+            }
+        })
+    }
+}
+
+/// How a surface reacts to light.
+///
+/// The surface color (the texel when there is a texture and the triangle has uvs,
+/// otherwise the triangle's color) is multiplied by `diffuse`, so the default material
+/// leaves it as is.
+#[derive(Debug, Clone)]
+pub struct Material<'a> {
+    pub diffuse: Color,
+    pub texture: Option<&'a Texture>,
+    /// Color of the highlights
+    pub specular: Color,
+    /// Specular exponent, higher is a smaller and sharper highlight. 0 disables the
+    /// highlights.
+    pub shininess: f32,
+    /// Light given off by the surface itself, added regardless of the lights
+    pub emissive: Color,
+    /// How much of the ambient light is reflected
+    pub ambient: f32,
+    /// Points where the texture's alpha is below this aren't drawn, for cut outs like
+    /// leaves or fences. 0, the default, draws the whole surface. Only for opaque
+    /// materials, the others blend by the alpha instead.
+    pub alpha_cutoff: f32,
+    pub blend: BlendMode,
+    /// Multiplies the texture's alpha when blending, 1 is opaque
+    pub opacity: f32,
+}
+
+impl<'a> Material<'a> {
+    pub fn new(diffuse: Color) -> Self {
+        Self {
+            diffuse,
+            ..Default::default()
+        }
+    }
+
+    pub fn textured(texture: &'a Texture) -> Self {
+        Self {
+            texture: Some(texture),
+            ..Default::default()
+        }
+    }
+
+    pub fn texture(mut self, texture: &'a Texture) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    pub fn specular(mut self, specular: Color, shininess: f32) -> Self {
+        self.specular = specular;
+        self.shininess = shininess;
+        self
+    }
+
+    pub fn emissive(mut self, emissive: Color) -> Self {
+        self.emissive = emissive;
+        self
+    }
+
+    pub fn ambient(mut self, ambient: f32) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn alpha_cutoff(mut self, alpha_cutoff: f32) -> Self {
+        self.alpha_cutoff = alpha_cutoff;
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    /// Final color of a point with the given surface color and illumination
+    pub fn shade(&self, surface: Color, illumination: &Illumination) -> Color {
+        let diffuse = surface
+            .to_vec3_f32s()
+            .component_wise_mul(&self.diffuse.to_vec3_f32s())
+            .component_wise_mul(&illumination.diffuse);
+        let specular = self
+            .specular
+            .to_vec3_f32s()
+            .component_wise_mul(&illumination.specular);
+        Color::from_vec3_f32s(diffuse + specular + self.emissive.to_vec3_f32s())
+    }
+}
+
+impl<'a> Default for Material<'a> {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::WHITE,
+            texture: None,
+            specular: Color::WHITE,
+            shininess: 50.0,
+            emissive: Color::BLACK,
+            ambient: 1.0,
+            alpha_cutoff: 0.0,
+            blend: BlendMode::Opaque,
+            opacity: 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shade() {
+        let illumination = Illumination {
+            diffuse: Vec3(1.0, 0.5, 0.0),
+            specular: Vec3(0.0, 0.0, 0.2),
+        };
+
+        // The default material only applies the lighting
+        assert_eq!(
+            Material::default().shade(Color(100, 100, 100), &illumination),
+            Color(100, 50, 51)
+        );
+
+        let material = Material::new(Color(255, 0, 255))
+            .specular(Color::BLACK, 0.0)
+            .emissive(Color(0, 20, 0));
+        assert_eq!(
+            material.shade(Color(100, 100, 100), &illumination),
+            Color(100, 20, 0)
+        );
+    }
+
+    #[test]
+    fn blend() {
+        let (source, destination) = (Color(200, 100, 0), Color(100, 100, 100));
+        let blend = |mode: BlendMode, alpha| mode.blend(source, alpha, destination);
+
+        assert_eq!(blend(BlendMode::Opaque, 0.5), source);
+        assert_eq!(blend(BlendMode::Alpha, 1.0), source);
+        assert_eq!(blend(BlendMode::Alpha, 0.5), Color(150, 100, 50));
+        assert_eq!(blend(BlendMode::Additive, 0.5), Color(200, 150, 100));
+        assert_eq!(blend(BlendMode::Multiply, 0.0), destination);
+        assert_eq!(blend(BlendMode::Multiply, 1.0), Color(78, 39, 0));
+    }
+}
@@ -82,6 +82,10 @@ impl Vec3<f32> {
         Vec4(self.0, self.1, self.2, 1.0)
     }
 
+    pub fn to_direction_vec4(&self) -> Vec4<f32> {
+        Vec4(self.0, self.1, self.2, 0.0)
+    }
+
     pub fn component_wise_mul(&self, rhs: &Vec3<f32>) -> Vec3<f32> {
         Vec3(self.0 * rhs.0, self.1 * rhs.1, self.2 * rhs.2)
     }
@@ -303,9 +307,13 @@ impl Degrees {
 pub struct Radians(f32);
 
 impl Radians {
-    pub fn new(val: f32) -> Self {
+    pub const fn new(val: f32) -> Self {
         Self(val)
     }
+
+    pub fn value(self) -> f32 {
+        self.0
+    }
 }
 
 impl From<Degrees> for Radians {
@@ -612,9 +620,41 @@ impl Mat4<f32> {
             0.0, 0.0, 0.0, 1.0
         )
     }
+
+    /// View matrix for a camera at `eye` looking at `target`. The camera looks down
+    /// its -z axis, same as the rest of the renderer. When looking along `up`, another
+    /// axis is used as up instead.
+    #[rustfmt::skip]
+    pub fn look_at(eye: Vec3<f32>, target: Vec3<f32>, up: Vec3<f32>) -> Self {
+        let z = (&eye - &target).normalize();
+        let mut x = up.cross(&z);
+        if x.magnitude() < 1e-6 {
+            let up = if z.2.abs() < 0.9 { Vec3(0.0, 0.0, -1.0) } else { Vec3(0.0, 1.0, 0.0) };
+            x = up.cross(&z);
+        }
+        let x = x.normalize();
+        let y = z.cross(&x);
+
+        Mat4::new(
+            x.0, x.1, x.2, -x.dot(&eye),
+            y.0, y.1, y.2, -y.dot(&eye),
+            z.0, z.1, z.2, -z.dot(&eye),
+            0.0, 0.0, 0.0, 1.0
+        )
+    }
 }
 
 impl<T: Copy> Mat4<T> {
+    pub fn transpose(&self) -> Mat4<T> {
+        let mut m = self.0;
+        for (col, column) in m.iter_mut().enumerate() {
+            for (row, value) in column.iter_mut().enumerate() {
+                *value = self.0[row][col];
+            }
+        }
+        Mat4(m)
+    }
+
     pub fn row(&self, i: u8) -> Vec4<T> {
         Vec4(self[(i, 0)], self[(i, 1)], self[(i, 2)], self[(i, 3)])
     }
@@ -811,4 +851,26 @@ mod test {
 
         assert_eq!(inverse, expected)
     }
+
+    #[test]
+    fn look_at() {
+        let view = Mat4::look_at(
+            Vec3(0.0, 0.0, 5.0),
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(0.0, 1.0, 0.0),
+        );
+        let expected = Mat4::translate(Vec3(0.0, 0.0, 5.0)).invert().unwrap();
+
+        assert_eq!(view, expected);
+
+        // Straight overhead, looking along the up vector
+        let view = Mat4::look_at(
+            Vec3(0.0, 5.0, 0.0),
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(0.0, 1.0, 0.0),
+        );
+        assert!(view.0.iter().flatten().all(|v| v.is_finite()));
+        let target = &view * Vec4(0.0, 0.0, 0.0, 1.0);
+        assert_eq!((target.0, target.1, target.2), (0.0, 0.0, -5.0));
+    }
 }
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, iter::Map, ops::Range, slice::Iter};
+
+use crate::{
+    material::Material,
+    math::{Radians, Vec2, Vec3},
+    normals::{generate_normals, position_key},
+    object::{Model, Triangle},
+    rasterize::Color,
+    texture::Texture,
+};
+
+#[derive(Clone, Debug)]
+pub struct Vertex {
+    pub position: Vec3<f32>,
+    pub normal: Option<Vec3<f32>>,
+    pub uv: Option<Vec2<f32>>,
+    pub color: Option<Color>,
+}
+
+/// Triangle of a [`Mesh`], refers to its corners by index into the mesh's vertices
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshTriangle {
+    pub indices: [u32; 3],
+    pub color: Color,
+    /// Index into the mesh's materials, like [`Triangle::material`]
+    pub material: Option<usize>,
+}
+
+/// Indexed triangle mesh, corners that are shared between triangles are only stored (and
+/// transformed by the rasterizer) once.
+#[derive(Clone, Debug)]
+pub struct Mesh<'a> {
+    pub vertices: Vec<Vertex>,
+    pub triangles: Vec<MeshTriangle>,
+    pub texture: Option<&'a Texture>,
+    pub materials: Vec<Material<'a>>,
+}
+
+impl<'a> Mesh<'a> {
+    pub fn new(vertices: Vec<Vertex>, triangles: Vec<MeshTriangle>) -> Self {
+        Self {
+            vertices,
+            triangles,
+            texture: None,
+            materials: vec![],
+        }
+    }
+
+    /// Build a mesh from expanded triangles, corners with the same position, normal and
+    /// uv become a single vertex
+    pub fn from_triangles(triangles: &[Triangle]) -> Self {
+        let mut vertices = vec![];
+        let mut indices = HashMap::new();
+        let mut mesh_triangles = Vec::with_capacity(triangles.len());
+
+        for t in triangles {
+            let corner_indices = [0, 1, 2].map(|corner| {
+                let vertex = Vertex {
+                    position: t[corner as u8].clone(),
+                    normal: t.normals.as_ref().map(|normals| normals[corner].clone()),
+                    uv: t.uvs.as_ref().map(|uvs| uvs[corner]),
+                    color: t.colors.map(|colors| colors[corner]),
+                };
+                let key = (
+                    position_key(&vertex.position),
+                    vertex.normal.as_ref().map(position_key),
+                    vertex.uv.map(|uv| [uv.0.to_bits(), uv.1.to_bits()]),
+                    vertex.color.map(|c| [c.0, c.1, c.2]),
+                );
+                *indices.entry(key).or_insert_with(|| {
+                    vertices.push(vertex);
+                    vertices.len() as u32 - 1
+                })
+            });
+            mesh_triangles.push(MeshTriangle {
+                indices: corner_indices,
+                color: t.color,
+                material: t.material,
+            });
+        }
+
+        Self::new(vertices, mesh_triangles)
+    }
+
+    pub fn with_texture(mut self, texture: &'a Texture) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    pub fn with_materials(mut self, materials: Vec<Material<'a>>) -> Self {
+        self.materials = materials;
+        self
+    }
+
+    /// Use `material` for a range of the mesh's triangles
+    pub fn set_material(&mut self, triangles: Range<usize>, material: Material<'a>) {
+        self.materials.push(material);
+        let index = self.materials.len() - 1;
+        for t in &mut self.triangles[triangles] {
+            t.material = Some(index);
+        }
+    }
+
+    /// Expanded copy of the `i`th triangle
+    pub fn triangle(&self, i: usize) -> Triangle {
+        let t = &self.triangles[i];
+        let [v0, v1, v2] = t.indices.map(|index| &self.vertices[index as usize]);
+        Triangle {
+            p0: v0.position.clone(),
+            p1: v1.position.clone(),
+            p2: v2.position.clone(),
+            color: t.color,
+            uvs: match (v0.uv, v1.uv, v2.uv) {
+                (Some(uv0), Some(uv1), Some(uv2)) => Some([uv0, uv1, uv2]),
+                _ => None,
+            },
+            normals: match (&v0.normal, &v1.normal, &v2.normal) {
+                (Some(n0), Some(n1), Some(n2)) => Some([n0.clone(), n1.clone(), n2.clone()]),
+                _ => None,
+            },
+            colors: match (v0.color, v1.color, v2.color) {
+                (Some(c0), Some(c1), Some(c2)) => Some([c0, c1, c2]),
+                _ => None,
+            },
+            material: t.material,
+        }
+    }
+
+    /// Copy of a range of the triangles as a mesh of their own, with the same texture and
+    /// materials
+    pub fn sub_mesh(&self, triangles: Range<usize>) -> Mesh<'a> {
+        let expanded: Vec<_> = triangles.map(|i| self.triangle(i)).collect();
+        Mesh {
+            texture: self.texture,
+            materials: self.materials.clone(),
+            ..Mesh::from_triangles(&expanded)
+        }
+    }
+
+    /// Replace the normals with generated ones, see [`generate_normals`]. Vertices are
+    /// split where the new normals differ.
+    pub fn generate_normals<R: Into<Radians>>(&mut self, crease_angle: R) {
+        let mut expanded: Vec<_> = (0..self.triangles.len())
+            .map(|i| self.triangle(i))
+            .collect();
+        generate_normals(&mut expanded, crease_angle.into());
+        let mesh = Mesh::from_triangles(&expanded);
+        self.vertices = mesh.vertices;
+        self.triangles = mesh.triangles;
+    }
+}
+
+/// Iterator over the expanded triangles of a [`Mesh`]
+pub struct MeshTriangles<'a> {
+    mesh: &'a Mesh<'a>,
+    next: usize,
+}
+
+impl<'a> Iterator for MeshTriangles<'a> {
+    type Item = Triangle;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.mesh.triangles.len() {
+            return None;
+        }
+        self.next += 1;
+        Some(self.mesh.triangle(self.next - 1))
+    }
+}
+
+impl<'a, 'b> Model<'a> for Mesh<'b> {
+    type VertexIter = Map<Iter<'a, Vertex>, fn(&Vertex) -> &Vec3<f32>>;
+
+    type TriangleIter = MeshTriangles<'a>;
+
+    fn vertices(&'a self) -> Self::VertexIter {
+        self.vertices.iter().map(|v| &v.position)
+    }
+
+    fn triangles(&'a self) -> Self::TriangleIter {
+        MeshTriangles {
+            mesh: self,
+            next: 0,
+        }
+    }
+
+    fn texture(&'a self) -> Option<&'a Texture> {
+        self.texture
+    }
+
+    fn materials(&'a self) -> &'a [Material<'a>] {
+        &self.materials
+    }
+
+    fn mesh(&'a self) -> Option<&'a Mesh<'a>> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shares_vertices() {
+        let quad = [
+            Triangle::new(
+                Vec3(0.0, 0.0, 0.0),
+                Vec3(1.0, 0.0, 0.0),
+                Vec3(1.0, 1.0, 0.0),
+                Color::RED,
+                None,
+            ),
+            Triangle::new(
+                Vec3(0.0, 0.0, 0.0),
+                Vec3(1.0, 1.0, 0.0),
+                Vec3(0.0, 1.0, 0.0),
+                Color::RED,
+                None,
+            ),
+        ];
+        let mesh = Mesh::from_triangles(&quad);
+
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles[1].indices, [0, 2, 3]);
+        let t = mesh.triangles().nth(1).unwrap();
+        assert_eq!((t.p2.0, t.p2.1, t.color), (0.0, 1.0, Color::RED));
+    }
+}
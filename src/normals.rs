@@ -0,0 +1,128 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{
+    math::{Radians, Vec3},
+    object::Triangle,
+};
+
+/// Default crease angle, edges sharper than this stay sharp
+pub const DEFAULT_CREASE_ANGLE: Radians = Radians::new(std::f32::consts::FRAC_PI_3);
+
+/// Generate smooth vertex normals for a triangle soup, replacing any normals the triangles
+/// already have. Corners at the same position share their normal, unless the angle
+/// between the faces is larger than `crease_angle`.
+pub fn generate_normals(triangles: &mut [Triangle], crease_angle: Radians) {
+    let keys: Vec<_> = triangles
+        .iter()
+        .map(|t| {
+            [
+                position_key(&t.p0),
+                position_key(&t.p1),
+                position_key(&t.p2),
+            ]
+        })
+        .collect();
+    let normals = smooth_normals(triangles, &keys, crease_angle);
+    for (t, normals) in triangles.iter_mut().zip(normals) {
+        t.normals = Some(normals);
+    }
+}
+
+/// Hashable position, for finding corners at the same position
+pub(crate) fn position_key(p: &Vec3<f32>) -> [u32; 3] {
+    // +0.0 so that -0.0 and 0.0 are the same position
+    [
+        (p.0 + 0.0).to_bits(),
+        (p.1 + 0.0).to_bits(),
+        (p.2 + 0.0).to_bits(),
+    ]
+}
+
+/// Normals for the corners of `triangles`, corners with the same key are smoothed
+/// together. Each face contributes its normal weighted by its area and the angle of its
+/// corner, so small slivers and fan triangulations don't skew the result.
+pub(crate) fn smooth_normals<K: Hash + Eq>(
+    triangles: &[Triangle],
+    keys: &[[K; 3]],
+    crease_angle: Radians,
+) -> Vec<[Vec3<f32>; 3]> {
+    // The cross product's length is twice the area, which is the weight we want
+    let face_normals: Vec<_> = triangles.iter().map(Triangle::normal).collect();
+    let unit_normals: Vec<_> = face_normals.iter().map(normalize_or_zero).collect();
+    let corner_angles: Vec<_> = triangles.iter().map(corner_angles).collect();
+
+    let mut corners: HashMap<&K, Vec<(usize, usize)>> = HashMap::new();
+    for (i, triangle_keys) in keys.iter().enumerate() {
+        for (corner, key) in triangle_keys.iter().enumerate() {
+            corners.entry(key).or_default().push((i, corner));
+        }
+    }
+
+    let cos_crease = crease_angle.value().cos();
+    keys.iter()
+        .enumerate()
+        .map(|(i, triangle_keys)| {
+            [0, 1, 2].map(|corner| {
+                let mut normal = Vec3(0.0, 0.0, 0.0);
+                for &(j, j_corner) in &corners[&triangle_keys[corner]] {
+                    if unit_normals[i].dot(&unit_normals[j]) >= cos_crease || i == j {
+                        normal = normal + &face_normals[j] * corner_angles[j][j_corner];
+                    }
+                }
+                match normal.magnitude() {
+                    m if m > 0.0 => normal * (1.0 / m),
+                    _ => unit_normals[i].clone(),
+                }
+            })
+        })
+        .collect()
+}
+
+fn normalize_or_zero(v: &Vec3<f32>) -> Vec3<f32> {
+    match v.magnitude() {
+        m if m > 0.0 => v * (1.0 / m),
+        _ => Vec3(0.0, 0.0, 0.0),
+    }
+}
+
+fn corner_angles(t: &Triangle) -> [f32; 3] {
+    let angle = |a: &Vec3<f32>, b: &Vec3<f32>, c: &Vec3<f32>| {
+        let (u, v) = (normalize_or_zero(&(b - a)), normalize_or_zero(&(c - a)));
+        u.dot(&v).clamp(-1.0, 1.0).acos()
+    };
+    [
+        angle(&t.p0, &t.p1, &t.p2),
+        angle(&t.p1, &t.p2, &t.p0),
+        angle(&t.p2, &t.p0, &t.p1),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{math::Degrees, rasterize::Color};
+
+    fn triangle(p0: (f32, f32, f32), p1: (f32, f32, f32), p2: (f32, f32, f32)) -> Triangle {
+        Triangle::new(p0.into(), p1.into(), p2.into(), Color::WHITE, None)
+    }
+
+    #[test]
+    fn smooth_and_crease() {
+        // Two triangles folded 90 degrees along the x axis
+        let mut triangles = [
+            triangle((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)),
+            triangle((1.0, 0.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+        ];
+
+        generate_normals(&mut triangles, Degrees(100.0).into());
+        let shared = &triangles[0].normals.as_ref().unwrap()[0];
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((shared.0 - 0.0).abs() < 1e-6);
+        assert!((shared.1 - s).abs() < 1e-6);
+        assert!((shared.2 - s).abs() < 1e-6);
+
+        generate_normals(&mut triangles, DEFAULT_CREASE_ANGLE);
+        let sharp = &triangles[0].normals.as_ref().unwrap()[0];
+        assert_eq!((sharp.0, sharp.1, sharp.2), (0.0, 0.0, 1.0));
+    }
+}
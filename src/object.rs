@@ -1,15 +1,22 @@
 use std::{
+    borrow::Borrow,
+    collections::HashMap,
     iter::{Flatten, Map},
-    ops::Index,
+    ops::{Index, Range},
+    path::PathBuf,
     slice::Iter,
 };
 
 use crate::{
+    cache,
     light::Shading,
+    material::{BlendMode, Material},
     math::{Mat4, Radians, Vec2, Vec3, Vec4},
+    mesh::Mesh,
+    normals::generate_normals,
     rasterize::Color,
     texture::Texture,
-    wavefront::WavefrontObj,
+    wavefront::{ObjError, WavefrontObj},
 };
 
 pub struct InstanceBuilder<'a, M: Model<'a>> {
@@ -19,6 +26,8 @@ pub struct InstanceBuilder<'a, M: Model<'a>> {
     rotation_y: Option<Radians>,
     color: Option<Color>,
     shading: Option<Shading>,
+    blend: Option<BlendMode>,
+    opacity: Option<f32>,
 }
 
 impl<'a, M: Model<'a>> InstanceBuilder<'a, M> {
@@ -30,6 +39,8 @@ impl<'a, M: Model<'a>> InstanceBuilder<'a, M> {
             rotation_y: None,
             color: None,
             shading: None,
+            blend: None,
+            opacity: None,
         }
     }
 
@@ -58,6 +69,18 @@ impl<'a, M: Model<'a>> InstanceBuilder<'a, M> {
         self
     }
 
+    /// Blend the whole model this way, instead of how its materials do
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = Some(blend);
+        self
+    }
+
+    /// Multiplies the opacity of the model's materials
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.opacity = Some(opacity);
+        self
+    }
+
     pub fn build(self) -> Instance<'a, M> {
         let pos = self.pos.unwrap_or_else(|| Vec3(0.0, 0.0, 0.0));
         let scale = self.scale.unwrap_or_else(|| Vec3(1.0, 1.0, 1.0));
@@ -73,6 +96,8 @@ impl<'a, M: Model<'a>> InstanceBuilder<'a, M> {
             rotation_y,
             color: self.color.unwrap_or(Color(0, 0, 0)),
             shading: self.shading.unwrap_or_default(),
+            blend: self.blend,
+            opacity: self.opacity.unwrap_or(1.0),
             transform_matrix,
             matrix_needs_update: false,
         }
@@ -87,6 +112,8 @@ pub struct Instance<'a, M: Model<'a>> {
     rotation_y: Radians,
     shading: Shading,
     color: Color,
+    blend: Option<BlendMode>,
+    opacity: f32,
 
     matrix_needs_update: bool,
     pub transform_matrix: Mat4<f32>,
@@ -130,6 +157,23 @@ impl<'a, M: Model<'a>> Instance<'a, M> {
         self.shading
     }
 
+    /// The blend mode overriding the materials' ones, if any
+    pub fn blend(&self) -> Option<BlendMode> {
+        self.blend
+    }
+
+    pub fn set_blend(&mut self, blend: Option<BlendMode>) {
+        self.blend = blend;
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
     fn build_transform_matrix(pos: Vec3<f32>, scale: Vec3<f32>, rotation_y: Radians) -> Mat4<f32> {
         Mat4::translate(pos)
             * Mat4::rotate_y_axis(rotation_y, Vec3(0.0, 0.0, 0.0))
@@ -139,11 +183,24 @@ impl<'a, M: Model<'a>> Instance<'a, M> {
 
 pub trait Model<'a> {
     type VertexIter: Iterator<Item = &'a Vec3<f32>>;
-    type TriangleIter: Iterator<Item = &'a Triangle>;
+    /// Yields references for models that store their triangles, and owned triangles for
+    /// ones that build them on the fly (like [`Mesh`])
+    type TriangleIter: Iterator<Item: Borrow<Triangle>>;
 
     fn vertices(&'a self) -> Self::VertexIter;
     fn triangles(&'a self) -> Self::TriangleIter;
     fn texture(&'a self) -> Option<&'a Texture>;
+
+    /// Materials the triangles refer to with [`Triangle::material`]
+    fn materials(&'a self) -> &'a [Material<'a>] {
+        &[]
+    }
+
+    /// The model as an indexed mesh, which lets the rasterizer transform shared vertices
+    /// only once
+    fn mesh(&'a self) -> Option<&'a Mesh<'a>> {
+        None
+    }
 }
 
 #[derive(Default)]
@@ -159,6 +216,11 @@ pub struct Triangle {
     pub color: Color,
     pub uvs: Option<[Vec2<f32>; 3]>,
     pub normals: Option<[Vec3<f32>; 3]>,
+    /// Colors of the corners, interpolated across the triangle instead of using `color`
+    pub colors: Option<[Color; 3]>,
+    /// Index into the model's [`Model::materials`], triangles without one use the
+    /// default material with the model's texture
+    pub material: Option<usize>,
 }
 
 impl Triangle {
@@ -176,6 +238,8 @@ impl Triangle {
             color,
             normals: normals,
             uvs: None,
+            colors: None,
+            material: None,
         }
     }
 
@@ -197,10 +261,14 @@ impl Triangle {
                 uvs[2].clone().into(),
             ]),
             normals: normals,
+            colors: None,
+            material: None,
         }
     }
 
-    pub fn transform(&self, m: &Mat4<f32>, view_proj: &Mat4<f32>) -> Self {
+    /// Transform the triangle's vertices by `m`, and its normals by `normal_matrix` (the
+    /// inverse transpose of `m`, so non-uniform scaling doesn't skew them)
+    pub fn transform(&self, m: &Mat4<f32>, normal_matrix: &Mat4<f32>) -> Self {
         let p0 = m * Vec4(self.p0.0, self.p0.1, self.p0.2, 1.0);
         let p1 = m * Vec4(self.p1.0, self.p1.1, self.p1.2, 1.0);
         let p2 = m * Vec4(self.p2.0, self.p2.1, self.p2.2, 1.0);
@@ -209,15 +277,16 @@ impl Triangle {
             p1: Vec3(p1.0, p1.1, p1.2),
             p2: Vec3(p2.0, p2.1, p2.2),
             color: self.color,
+            material: self.material,
             uvs: self.uvs.clone(),
+            colors: self.colors,
             normals: self.normals.clone().map(|normals| {
                 [
-                    (view_proj * normals[0].to_point_vec4()).drop_fourth_component(),
-                    (view_proj * normals[1].to_point_vec4()).drop_fourth_component(),
-                    (view_proj * normals[2].to_point_vec4()).drop_fourth_component(),
+                    (normal_matrix * normals[0].to_direction_vec4()).drop_fourth_component(),
+                    (normal_matrix * normals[1].to_direction_vec4()).drop_fourth_component(),
+                    (normal_matrix * normals[2].to_direction_vec4()).drop_fourth_component(),
                 ]
             }),
-            // normals: self.normals.clone(),
         }
     }
 
@@ -260,7 +329,7 @@ impl<'a> Model<'a> for Triangle {
     }
 
     fn texture(&'a self) -> Option<&'a Texture> {
-        todo!()
+        None
     }
 }
 
@@ -270,6 +339,7 @@ pub struct Cube<'a> {
     back: [Vec3<f32>; 4],
     triangles: [Triangle; 12],
     texture: Option<&'a Texture>,
+    materials: Vec<Material<'a>>,
 }
 
 fn map_triangle(t: &Triangle) -> [&Vec3<f32>; 3] {
@@ -293,9 +363,39 @@ impl<'a, 'b> Model<'a> for Cube<'b> {
     fn texture(&'a self) -> Option<&'a Texture> {
         self.texture
     }
+
+    fn materials(&'a self) -> &'a [Material<'a>] {
+        &self.materials
+    }
 }
 
 impl<'a> Cube<'a> {
+    /// Give the cube vertex normals, see [`generate_normals`]. With a crease angle below
+    /// 90 degrees the faces stay flat.
+    pub fn with_normals<R: Into<Radians>>(mut self, crease_angle: R) -> Self {
+        generate_normals(&mut self.triangles, crease_angle.into());
+        self
+    }
+
+    /// Use `material` for all the faces of the cube
+    pub fn with_material(mut self, material: Material<'a>) -> Self {
+        self.materials = vec![material];
+        for t in &mut self.triangles {
+            t.material = Some(0);
+        }
+        self
+    }
+
+    /// The cube as an indexed mesh, corners shared by faces with the same normals and
+    /// uvs become a single vertex
+    pub fn to_mesh(&self) -> Mesh<'a> {
+        Mesh {
+            texture: self.texture,
+            materials: self.materials.clone(),
+            ..Mesh::from_triangles(&self.triangles)
+        }
+    }
+
     pub fn new_with_texture<V: Into<Vec2<f32>> + Clone>(
         ftl: Vec3<f32>,
         fbl: Vec3<f32>,
@@ -317,6 +417,7 @@ impl<'a> Cube<'a> {
             back: [btl, bbl, bbr, btr],
             triangles,
             texture: Some(tex),
+            materials: vec![],
         }
     }
 
@@ -349,6 +450,7 @@ impl<'a> Cube<'a> {
             back: [btl, bbl, bbr, btr],
             triangles,
             texture: None,
+            materials: vec![],
         }
     }
 
@@ -419,58 +521,181 @@ impl<'a> Cube<'a> {
     }
 }
 
+/// Named range of a model's triangles, from the `o` and `g` statements of an obj file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part {
+    pub name: String,
+    pub triangles: Range<usize>,
+}
+
 #[derive(Clone, Debug)]
 pub struct WavefrontModel<'a> {
-    triangles: Vec<Triangle>,
-    texture: Option<&'a Texture>,
+    mesh: Mesh<'a>,
+    parts: Vec<Part>,
 }
 
 impl<'a> WavefrontModel<'a> {
-    pub fn new(obj: WavefrontObj, color: Color, outlines: bool) -> Self {
-        let triangles = obj.make_triangles(Some(color), false, false, outlines);
-        Self {
-            triangles,
-            texture: None,
-        }
+    pub fn new(obj: WavefrontObj, color: Color, outlines: bool) -> Result<Self, ObjError> {
+        let (triangles, parts) = obj.make_parts(Some(color), false, false, outlines)?;
+        Ok(Self {
+            mesh: Mesh::from_triangles(&triangles),
+            parts,
+        })
+    }
+
+    /// Like [`Self::new`], with the obj's normals, and generated ones for the faces that
+    /// don't have any (see [`WavefrontObj::with_crease_angle`])
+    pub fn new_with_normals(
+        obj: WavefrontObj,
+        color: Color,
+        outlines: bool,
+    ) -> Result<Self, ObjError> {
+        let (triangles, parts) = obj.make_parts(Some(color), false, true, outlines)?;
+        Ok(Self {
+            mesh: Mesh::from_triangles(&triangles),
+            parts,
+        })
+    }
+
+    pub fn new_with_tex(
+        obj: WavefrontObj,
+        texture: &'a Texture,
+        normals: bool,
+    ) -> Result<Self, ObjError> {
+        let (triangles, parts) = obj.make_parts(None, true, normals, false)?;
+        Ok(Self {
+            mesh: Mesh::from_triangles(&triangles).with_texture(texture),
+            parts,
+        })
+    }
+
+    /// Model using the materials from the obj's material libraries, `textures` are the
+    /// diffuse maps loaded with [`WavefrontObj::load_textures`]
+    pub fn new_with_materials(
+        obj: WavefrontObj,
+        textures: &'a HashMap<PathBuf, Texture>,
+    ) -> Result<Self, ObjError> {
+        let (triangles, parts) =
+            obj.make_parts(Some(Color::WHITE), obj.has_uvs(), obj.has_normals(), false)?;
+        let materials = obj
+            .materials()
+            .iter()
+            .map(|m| Material {
+                diffuse: m.diffuse_color(),
+                texture: m.diffuse_map.as_ref().and_then(|path| textures.get(path)),
+                specular: m.specular_color(),
+                shininess: m.shininess,
+                emissive: Color::BLACK,
+                ambient: (m.ambient.0 + m.ambient.1 + m.ambient.2) / 3.0,
+                blend: match m.dissolve < 1.0 {
+                    true => BlendMode::Alpha,
+                    false => BlendMode::Opaque,
+                },
+                opacity: m.dissolve,
+                ..Default::default()
+            })
+            .collect();
+        Ok(Self {
+            mesh: Mesh::from_triangles(&triangles).with_materials(materials),
+            parts,
+        })
+    }
+
+    /// Load a model from the binary mesh cache, see [`cache`]. `textures` are the
+    /// textures of its materials, by the paths from [`cache::texture_paths`].
+    pub fn from_cache(
+        bytes: &[u8],
+        textures: &'a HashMap<PathBuf, Texture>,
+    ) -> Result<Self, String> {
+        let (mesh, parts) = cache::decode(bytes, textures)?;
+        Ok(Self { mesh, parts })
+    }
+
+    /// Serialize the model to the binary mesh cache format, `textures` are the ones the
+    /// model was made with (so their paths can be stored)
+    pub fn to_cache(&self, textures: &HashMap<PathBuf, Texture>) -> Result<Vec<u8>, String> {
+        cache::encode(&self.mesh, &self.parts, textures)
+    }
+
+    /// Texture for the triangles that don't have a material with a texture of its own
+    pub fn with_texture(mut self, texture: &'a Texture) -> Self {
+        self.mesh.texture = Some(texture);
+        self
     }
 
-    pub fn new_with_tex(obj: WavefrontObj, texture: &'a Texture, normals: bool) -> Self {
-        let triangles = obj.make_triangles(None, true, normals, false);
-        Self {
-            triangles,
-            texture: Some(texture),
-        }
+    /// Use `material` for the whole model
+    pub fn with_material(mut self, material: Material<'a>) -> Self {
+        self.mesh.materials.clear();
+        self.set_material(0..self.triangle_count(), material);
+        self
+    }
+
+    /// Use `material` for a range of the model's triangles
+    pub fn set_material(&mut self, triangles: Range<usize>, material: Material<'a>) {
+        self.mesh.set_material(triangles, material);
+    }
+
+    /// Replace the model's normals with generated ones, see [`generate_normals`]
+    pub fn generate_normals<R: Into<Radians>>(&mut self, crease_angle: R) {
+        self.mesh.generate_normals(crease_angle);
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.mesh.triangles.len()
+    }
+
+    pub fn mesh(&self) -> &Mesh<'a> {
+        &self.mesh
+    }
+
+    /// The objects and groups of the obj file, use [`Self::set_material`] with a part's
+    /// range to color it
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+
+    /// Split into a model per part, so parts can be hidden or transformed individually
+    /// by rendering them as separate instances
+    pub fn split(&self) -> Vec<WavefrontModel<'a>> {
+        self.parts
+            .iter()
+            .map(|part| WavefrontModel {
+                mesh: self.mesh.sub_mesh(part.triangles.clone()),
+                parts: vec![Part {
+                    name: part.name.clone(),
+                    triangles: 0..part.triangles.len(),
+                }],
+            })
+            .collect()
     }
 }
 
-impl<'a> Model<'a> for WavefrontModel<'a> {
-    type VertexIter = Flatten<
-        Map<
-            Iter<'a, Triangle>,
-            for<'r> fn(
-                &'r Triangle,
-            ) -> std::iter::Chain<
-                std::iter::Chain<std::iter::Once<&Vec3<f32>>, std::iter::Once<&Vec3<f32>>>,
-                std::iter::Once<&Vec3<f32>>,
-            >,
-        >,
-    >;
+impl<'a, 'b> Model<'a> for WavefrontModel<'b> {
+    type VertexIter = <Mesh<'b> as Model<'a>>::VertexIter;
 
-    type TriangleIter = Iter<'a, Triangle>;
+    type TriangleIter = <Mesh<'b> as Model<'a>>::TriangleIter;
 
     fn vertices(&'a self) -> Self::VertexIter {
-        self.triangles.iter().map(triangle_vertices as _).flatten()
+        self.mesh.vertices()
     }
 
     fn triangles(&'a self) -> Self::TriangleIter {
-        self.triangles.iter()
+        self.mesh.triangles()
     }
 
     fn texture(&'a self) -> Option<&'a Texture> {
-        self.texture
+        self.mesh.texture
+    }
+
+    fn materials(&'a self) -> &'a [Material<'a>] {
+        &self.mesh.materials
     }
-}
 
-fn triangle_vertices(t: &Triangle) -> <Triangle as Model>::VertexIter {
-    t.vertices()
+    fn mesh(&'a self) -> Option<&'a Mesh<'a>> {
+        Some(&self.mesh)
+    }
 }
@@ -0,0 +1,435 @@
+use std::{fs, path::Path, str::SplitAsciiWhitespace};
+
+use crate::{
+    math::{Vec2, Vec3},
+    object::Triangle,
+    rasterize::Color,
+    triangulate::triangulate,
+};
+
+/// Load an ascii or binary PLY file. Vertex normals, colors and texture coordinates are
+/// used when the file has them, `color` is the color of the triangles without vertex
+/// colors.
+pub fn load_ply<P: AsRef<Path>>(path: P, color: Color) -> Result<Vec<Triangle>, String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_ply(&bytes, color)
+}
+
+pub fn parse_ply(bytes: &[u8], color: Color) -> Result<Vec<Triangle>, String> {
+    let (header, body) = Header::parse(bytes)?;
+    let mut values = match header.format {
+        Format::Ascii => Values::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|e| e.to_string())?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Values::Binary {
+            bytes: body,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Values::Binary {
+            bytes: body,
+            big_endian: true,
+        },
+    };
+
+    let mut vertices = vec![];
+    let mut faces = vec![];
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => vertices = read_vertices(element, &mut values)?,
+            "face" => faces = read_faces(element, &mut values)?,
+            _ => {
+                for _ in 0..element.count {
+                    element.skip(&mut values)?;
+                }
+            }
+        }
+    }
+
+    let mut triangles = vec![];
+    for face in faces {
+        let corners = face
+            .iter()
+            .map(|&i| {
+                vertices
+                    .get(i)
+                    .ok_or_else(|| format!("Vertex index {} is out of range", i))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let points: Vec<_> = corners.iter().map(|v| v.position.clone()).collect();
+
+        for [c0, c1, c2] in triangulate(&points) {
+            let [v0, v1, v2] = [corners[c0], corners[c1], corners[c2]];
+            triangles.push(Triangle {
+                p0: v0.position.clone(),
+                p1: v1.position.clone(),
+                p2: v2.position.clone(),
+                color,
+                uvs: match (v0.uv, v1.uv, v2.uv) {
+                    (Some(uv0), Some(uv1), Some(uv2)) => Some([uv0, uv1, uv2]),
+                    _ => None,
+                },
+                normals: match (&v0.normal, &v1.normal, &v2.normal) {
+                    (Some(n0), Some(n1), Some(n2)) => Some([n0.clone(), n1.clone(), n2.clone()]),
+                    _ => None,
+                },
+                colors: match (v0.color, v1.color, v2.color) {
+                    (Some(c0), Some(c1), Some(c2)) => Some([c0, c1, c2]),
+                    _ => None,
+                },
+                material: None,
+            });
+        }
+    }
+
+    Ok(triangles)
+}
+
+struct Vertex {
+    position: Vec3<f32>,
+    normal: Option<Vec3<f32>>,
+    uv: Option<Vec2<f32>>,
+    color: Option<Color>,
+}
+
+fn read_vertices(element: &Element, values: &mut Values) -> Result<Vec<Vertex>, String> {
+    let find = |names: &[&str]| {
+        element.properties.iter().position(|p| match p {
+            Property::Scalar(_, name) => names.contains(&name.as_str()),
+            Property::List(..) => false,
+        })
+    };
+    let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+    let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+    let uv = [
+        find(&["u", "s", "texture_u", "texture_s"]),
+        find(&["v", "t", "texture_v", "texture_t"]),
+    ];
+    let color = [
+        find(&["red", "r", "diffuse_red"]),
+        find(&["green", "g", "diffuse_green"]),
+        find(&["blue", "b", "diffuse_blue"]),
+    ];
+    let [Some(x), Some(y), Some(z)] = position else {
+        return Err("PLY vertices without x, y and z".to_string());
+    };
+
+    // Not reserved up front, the count comes from the header and may be anything
+    let mut vertices = vec![];
+    let mut row = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, property) in row.iter_mut().zip(&element.properties) {
+            *value = match property {
+                Property::Scalar(ty, _) => values.read(*ty)?,
+                Property::List(count_ty, ty, _) => {
+                    let count = values.read(*count_ty)? as usize;
+                    for _ in 0..count {
+                        values.read(*ty)?;
+                    }
+                    0.0
+                }
+            };
+        }
+        let component = |i: usize| row[i] as f32;
+        // Floating point colors go from 0 to 1, integer ones from 0 to 255
+        let color_component = |i: usize| match &element.properties[i] {
+            Property::Scalar(ty, _) if ty.is_float() => (row[i] * 255.0).clamp(0.0, 255.0) as u8,
+            _ => row[i].clamp(0.0, 255.0) as u8,
+        };
+
+        vertices.push(Vertex {
+            position: Vec3(component(x), component(y), component(z)),
+            normal: match normal {
+                [Some(nx), Some(ny), Some(nz)] => {
+                    Some(Vec3(component(nx), component(ny), component(nz)))
+                }
+                _ => None,
+            },
+            uv: match uv {
+                [Some(u), Some(v)] => Some(Vec2(component(u), component(v))),
+                _ => None,
+            },
+            color: match color {
+                [Some(r), Some(g), Some(b)] => Some(Color(
+                    color_component(r),
+                    color_component(g),
+                    color_component(b),
+                )),
+                _ => None,
+            },
+        });
+    }
+
+    Ok(vertices)
+}
+
+fn read_faces(element: &Element, values: &mut Values) -> Result<Vec<Vec<usize>>, String> {
+    let mut faces = vec![];
+    for _ in 0..element.count {
+        let mut face = None;
+        for property in &element.properties {
+            match property {
+                Property::List(count_ty, ty, name)
+                    if name == "vertex_indices" || name == "vertex_index" =>
+                {
+                    let count = values.read(*count_ty)? as usize;
+                    face = Some(
+                        (0..count)
+                            .map(|_| match values.read(*ty)? {
+                                i if i >= 0.0 && i.fract() == 0.0 => Ok(i as usize),
+                                i => Err(format!("Invalid vertex index {} in PLY file", i)),
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                    );
+                }
+                property => property.skip(values)?,
+            }
+        }
+        faces.push(face.ok_or_else(|| "PLY faces without vertex_indices".to_string())?);
+    }
+    Ok(faces)
+}
+
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(format!("Unknown PLY type: {}", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+}
+
+enum Property {
+    Scalar(Scalar, String),
+    /// Type of the count, type of the items and the name
+    List(Scalar, Scalar, String),
+}
+
+impl Property {
+    fn skip(&self, values: &mut Values) -> Result<(), String> {
+        match self {
+            Property::Scalar(ty, _) => {
+                values.read(*ty)?;
+            }
+            Property::List(count_ty, ty, _) => {
+                let count = values.read(*count_ty)? as usize;
+                for _ in 0..count {
+                    values.read(*ty)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn skip(&self, values: &mut Values) -> Result<(), String> {
+        self.properties.iter().try_for_each(|p| p.skip(values))
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    /// Parse the header, returning it and the rest of the file
+    fn parse(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
+        let mut format = None;
+        let mut elements: Vec<Element> = vec![];
+        let mut rest = bytes;
+
+        for number in 1.. {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or_else(|| "PLY header without end_header".to_string())?;
+            let line = String::from_utf8_lossy(&rest[..end]);
+            rest = &rest[end + 1..];
+            let words: Vec<_> = line.split_whitespace().collect();
+            let error = || format!("Line {}: invalid PLY header line: {}", number, line.trim());
+
+            match words.as_slice() {
+                ["ply"] if number == 1 => {}
+                _ if number == 1 => return Err("Not a PLY file".to_string()),
+                ["format", kind, _version] => {
+                    format = Some(match *kind {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::BinaryLittleEndian,
+                        "binary_big_endian" => Format::BinaryBigEndian,
+                        _ => return Err(error()),
+                    })
+                }
+                ["element", name, count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| error())?,
+                    properties: vec![],
+                }),
+                ["property", "list", count_ty, ty, name] => elements
+                    .last_mut()
+                    .ok_or_else(error)?
+                    .properties
+                    .push(Property::List(
+                        Scalar::parse(count_ty)?,
+                        Scalar::parse(ty)?,
+                        name.to_string(),
+                    )),
+                ["property", ty, name] => elements
+                    .last_mut()
+                    .ok_or_else(error)?
+                    .properties
+                    .push(Property::Scalar(Scalar::parse(ty)?, name.to_string())),
+                ["end_header"] => break,
+                ["comment", ..] | ["obj_info", ..] | [] => {}
+                _ => return Err(error()),
+            }
+        }
+
+        let format = format.ok_or_else(|| "PLY header without a format".to_string())?;
+        Ok((Self { format, elements }, rest))
+    }
+}
+
+/// The values of the body of a PLY file, read one at a time
+enum Values<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl<'a> Values<'a> {
+    fn read(&mut self, ty: Scalar) -> Result<f64, String> {
+        match self {
+            Values::Ascii(words) => words
+                .next()
+                .ok_or_else(|| "Unexpected end of PLY file".to_string())?
+                .parse()
+                .map_err(|_| "Invalid number in PLY file".to_string()),
+            Values::Binary { bytes, big_endian } => {
+                if bytes.len() < ty.size() {
+                    return Err("Unexpected end of PLY file".to_string());
+                }
+                let (value, rest) = bytes.split_at(ty.size());
+                *bytes = rest;
+                let mut buffer = [0; 8];
+                buffer[..value.len()].copy_from_slice(value);
+                if *big_endian {
+                    buffer[..value.len()].reverse();
+                }
+                Ok(match ty {
+                    Scalar::I8 => buffer[0] as i8 as f64,
+                    Scalar::U8 => buffer[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    Scalar::I32 => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+                    Scalar::U32 => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+                    Scalar::F32 => f32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn colored_quad() {
+        let header = "ply
+format {}
+comment a quad with vertex colors
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+        let ascii = header.replace("{}", "ascii 1.0")
+            + "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n";
+
+        let mut binary = header.replace("{}", "binary_big_endian 1.0").into_bytes();
+        for (x, y, color) in [(0.0f32, 0.0f32, [255, 0, 0]), (1.0, 0.0, [0, 255, 0])]
+            .into_iter()
+            .chain([(1.0, 1.0, [0, 0, 255]), (0.0, 1.0, [255, 255, 255])])
+        {
+            for f in [x, y, 0.0] {
+                binary.extend(f.to_be_bytes());
+            }
+            binary.extend(color);
+        }
+        binary.push(4);
+        for i in 0..4i32 {
+            binary.extend(i.to_be_bytes());
+        }
+
+        for bytes in [ascii.as_bytes(), &binary] {
+            let triangles = parse_ply(bytes, Color::BLACK).unwrap();
+            assert_eq!(triangles.len(), 2);
+            assert_eq!(
+                triangles[1].colors,
+                Some([Color::RED, Color::BLUE, Color::WHITE])
+            );
+            assert_eq!((triangles[1].p2.0, triangles[1].p2.1), (0.0, 1.0));
+        }
+
+        for face in ["4 0 1 2 -3\n", "4 0 1 2 2.5\n"] {
+            let bytes = ascii.replace("4 0 1 2 3\n", face);
+            assert!(parse_ply(bytes.as_bytes(), Color::BLACK)
+                .unwrap_err()
+                .starts_with("Invalid vertex index"));
+        }
+    }
+}
@@ -10,6 +10,8 @@ impl Color {
     pub const RED: Self = Color(255, 0, 0);
     pub const GREEN: Self = Color(0, 255, 0);
     pub const BLUE: Self = Color(0, 0, 255);
+    pub const WHITE: Self = Color(255, 255, 255);
+    pub const BLACK: Self = Color(0, 0, 0);
 }
 
 impl Color {
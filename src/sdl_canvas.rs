@@ -47,6 +47,16 @@ impl<'a> Canvas for SDLCanvas<'a> {
         }
     }
 
+    fn get_pixel<X: IntoPixelValue, Y: IntoPixelValue>(&self, x: X, y: Y) -> Option<Color> {
+        let (x, y) = canvas_coords_to_screen_coords(x, y, self.width, self.height)?;
+        let i: usize = (y as usize * self.width as usize * 3) + (x as usize * 3);
+        Some(Color(
+            self.buffer[i],
+            self.buffer[i + 1],
+            self.buffer[i + 2],
+        ))
+    }
+
     fn clear(&mut self, color: Color) {
         let mut i = 0;
         while i < self.buffer.len() {
@@ -0,0 +1,111 @@
+use std::{fs, path::Path};
+
+use crate::{math::Vec3, object::Triangle, rasterize::Color};
+
+/// Load an ascii or binary STL file. STL only has flat faces, so the triangles get no
+/// vertex normals, see [`crate::normals::generate_normals`] to smooth them.
+pub fn load_stl<P: AsRef<Path>>(path: P, color: Color) -> Result<Vec<Triangle>, String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_stl(&bytes, color)
+}
+
+pub fn parse_stl(bytes: &[u8], color: Color) -> Result<Vec<Triangle>, String> {
+    // Binary files may also start with "solid", the size is the reliable check
+    let binary_count = bytes
+        .get(80..84)
+        .map(|count| u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    // Header, triangle count and 50 bytes per triangle, the count may be garbage
+    let binary_size =
+        binary_count.map(|count| (count, count.checked_mul(50).and_then(|n| n.checked_add(84))));
+    match binary_size {
+        Some((_, Some(size))) if bytes.len() == size => Ok(parse_binary(&bytes[84..], color)),
+        _ if bytes.trim_ascii_start().starts_with(b"solid") => parse_ascii(bytes, color),
+        Some((count, None)) => Err(format!("STL file with too many triangles: {}", count)),
+        _ => Err("Not an STL file".to_string()),
+    }
+}
+
+fn parse_binary(bytes: &[u8], color: Color) -> Vec<Triangle> {
+    let float = |b: &[u8], i: usize| f32::from_le_bytes(b[i * 4..i * 4 + 4].try_into().unwrap());
+    bytes
+        .chunks_exact(50)
+        .map(|facet| {
+            // Facet normal, three vertices and a 2 byte attribute count
+            let point = |i: usize| Vec3(float(facet, i), float(facet, i + 1), float(facet, i + 2));
+            Triangle::new(point(3), point(6), point(9), color, None)
+        })
+        .collect()
+}
+
+fn parse_ascii(bytes: &[u8], color: Color) -> Result<Vec<Triangle>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+    let mut triangles = vec![];
+    let mut facet = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let mut coordinate = || {
+                    words
+                        .next()
+                        .and_then(|w| w.parse::<f32>().ok())
+                        .ok_or_else(|| format!("Line {}: invalid vertex", number + 1))
+                };
+                facet.push(Vec3(coordinate()?, coordinate()?, coordinate()?));
+            }
+            Some("endfacet") => {
+                // Some exporters write polygons, split them into a fan
+                for i in 1..facet.len().saturating_sub(1) {
+                    triangles.push(Triangle::new(
+                        facet[0].clone(),
+                        facet[i].clone(),
+                        facet[i + 1].clone(),
+                        color,
+                        None,
+                    ));
+                }
+                facet.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ascii_and_binary() {
+        let ascii = "solid test
+            facet normal 0 0 1
+                outer loop
+                    vertex 0 0 0
+                    vertex 1 0 0
+                    vertex 0 1 0
+                endloop
+            endfacet
+        endsolid test";
+
+        let mut binary = b"solid but actually binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend(1u32.to_le_bytes());
+        for f in [
+            0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+        ] {
+            binary.extend(f.to_le_bytes());
+        }
+        binary.extend([0, 0]);
+
+        for bytes in [ascii.as_bytes(), &binary] {
+            let triangles = parse_stl(bytes, Color::RED).unwrap();
+            assert_eq!(triangles.len(), 1);
+            let t = &triangles[0];
+            assert_eq!((t.p1.0, t.p2.1, t.color), (1.0, 1.0, Color::RED));
+        }
+    }
+}
@@ -1,10 +1,81 @@
-use image::{GenericImageView, ImageFormat};
+use std::path::Path;
 
-use crate::rasterize::Color;
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbImage, RgbaImage};
 
+use crate::{
+    math::{Vec2, Vec3},
+    rasterize::Color,
+};
+
+/// How a texture is sampled between the centers of its texels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterMode {
+    /// The closest texel, keeps pixel art crisp
+    Nearest,
+    /// Blend of the four closest texels, smooth when magnified. When minified the two
+    /// closest mip levels are blended as well (trilinear filtering)
+    #[default]
+    Bilinear,
+}
+
+/// What uvs outside of 0..1 sample, set for u and v separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    /// The texture tiles
+    #[default]
+    Repeat,
+    /// The texture tiles, every other copy flipped so the edges line up
+    MirroredRepeat,
+    /// The edge texels stretch out forever
+    ClampToEdge,
+}
+
+impl WrapMode {
+    /// Index of the texel `i` maps to, in a row or column of `size` texels
+    fn apply(self, i: i64, size: u32) -> usize {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let i = i.rem_euclid(size * 2);
+                if i < size {
+                    i
+                } else {
+                    size * 2 - 1 - i
+                }
+            }
+            WrapMode::ClampToEdge => i.clamp(0, size - 1),
+        };
+        i as usize
+    }
+}
+
+/// Direction of a [`Texture::gradient`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gradient {
+    /// From the left edge to the right one
+    Horizontal,
+    /// From the top edge to the bottom one
+    Vertical,
+    /// From the center out to the corners
+    Radial,
+}
+
+/// An image along with its mip chain, each level half the size of the previous one down
+/// to 1x1
 #[derive(Debug, Clone)]
 pub struct Texture {
+    levels: Vec<MipLevel>,
+    filter: FilterMode,
+    wrap_u: WrapMode,
+    wrap_v: WrapMode,
+}
+
+#[derive(Debug, Clone)]
+struct MipLevel {
     pixels: Vec<Color>,
+    /// Alpha of each pixel, 0 is fully transparent. Empty when the image is opaque.
+    alpha: Vec<u8>,
     w: u32,
     h: u32,
 }
@@ -12,59 +83,572 @@ pub struct Texture {
 impl Texture {
     pub fn load(path: &str) -> Result<Texture, String> {
         let image = image::open(path).map_err(to_string)?;
+        Ok(Texture::from_image(&image))
+    }
 
+    pub fn from_bytes(bytes: &[u8], format: ImageFormat) -> Result<Self, String> {
+        let image = image::load_from_memory_with_format(bytes, format).map_err(to_string)?;
+        Ok(Texture::from_image(&image))
+    }
+
+    /// Keeps the alpha channel if the image has one
+    fn from_image(image: &DynamicImage) -> Self {
         let (w, h) = image.dimensions();
+        let rgba = image.to_rgba8();
+        let pixels = rgba.pixels().map(|p| Color(p[0], p[1], p[2])).collect();
+        let alpha = match image.color().has_alpha() {
+            true => rgba.pixels().map(|p| p[3]).collect(),
+            false => vec![],
+        };
+        Texture::from_pixels(pixels, alpha, w, h)
+    }
 
-        let mut pixels = Vec::with_capacity(w as usize * h as usize * 3);
+    /// Generates the mip chain, `pixels` are row by row, from the top. `alpha` is either
+    /// empty for an opaque texture or has a value per pixel.
+    fn from_pixels(pixels: Vec<Color>, alpha: Vec<u8>, w: u32, h: u32) -> Self {
+        assert!(alpha.is_empty() || alpha.len() == pixels.len());
+        let mut levels = vec![MipLevel {
+            pixels,
+            alpha,
+            w,
+            h,
+        }];
+        while let Some(level) = levels.last().filter(|l| l.w > 1 || l.h > 1) {
+            levels.push(level.downsample());
+        }
 
-        for y in 0..h {
-            for x in 0..w {
-                let pixel = image.get_pixel(x, y);
-                // pixels.push(pixel[0]);
-                // pixels.push(pixel[1]);
-                // pixels.push(pixel[2]);
-                pixels.push(Color(pixel[0], pixel[1], pixel[2]));
-            }
+        Texture {
+            levels,
+            filter: FilterMode::default(),
+            wrap_u: WrapMode::default(),
+            wrap_v: WrapMode::default(),
         }
+    }
 
-        Ok(Texture { pixels, w, h })
+    /// A texture where the color of each pixel is `f(x, y)`, from the top left. Panics if
+    /// `width` or `height` is 0.
+    pub fn from_fn<F: FnMut(u32, u32) -> Color>(width: u32, height: u32, mut f: F) -> Self {
+        assert!(width > 0 && height > 0, "Textures are at least 1x1");
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        Texture::from_pixels(pixels, vec![], width, height)
     }
 
-    pub fn from_bytes(bytes: &[u8], format: ImageFormat) -> Result<Self, String> {
-        let image = image::load_from_memory_with_format(bytes, format).map_err(to_string)?;
+    /// `cells` by `cells` squares of `cell_size` pixels, alternating between `a` (in the
+    /// top left corner) and `b`. Tiles seamlessly when `cells` is even. Panics if `cells`
+    /// or `cell_size` is 0.
+    pub fn checkerboard(cells: u32, cell_size: u32, a: Color, b: Color) -> Self {
+        let size = cells * cell_size;
+        Texture::from_fn(size, size, |x, y| {
+            match (x / cell_size + y / cell_size) % 2 {
+                0 => a,
+                _ => b,
+            }
+        })
+    }
 
-        let (w, h) = image.dimensions();
+    /// `cells` by `cells` squares of `cell_size` pixels of `background`, split by lines
+    /// `line_width` pixels wide. The lines are on the top and left sides of the squares,
+    /// so the grid tiles seamlessly. Panics if `cells` or `cell_size` is 0.
+    pub fn grid(
+        cells: u32,
+        cell_size: u32,
+        line_width: u32,
+        line: Color,
+        background: Color,
+    ) -> Self {
+        let size = cells * cell_size;
+        Texture::from_fn(size, size, |x, y| {
+            if x % cell_size < line_width || y % cell_size < line_width {
+                line
+            } else {
+                background
+            }
+        })
+    }
+
+    /// `width` by `height` pixels going from `from` to `to`. Panics if `width` or `height`
+    /// is 0.
+    pub fn gradient(width: u32, height: u32, gradient: Gradient, from: Color, to: Color) -> Self {
+        let (w, h) = (width as f32, height as f32);
+        Texture::from_fn(width, height, |x, y| {
+            // Measured at the pixel centers
+            let (u, v) = ((x as f32 + 0.5) / w, (y as f32 + 0.5) / h);
+            let t = match gradient {
+                Gradient::Horizontal => u,
+                Gradient::Vertical => v,
+                Gradient::Radial => (u - 0.5).hypot(v - 0.5) / 0.5f32.hypot(0.5),
+            };
+            mix(from, to, t)
+        })
+    }
 
-        let mut pixels = Vec::with_capacity(w as usize * h as usize * 3);
+    /// Smooth random blobs, `cells` by `cells` across, colored from `low` to `high`. Each
+    /// of the `octaves` adds detail at twice the frequency and half the strength of the
+    /// previous one, as many as fit before the cell count overflows. The same `seed` gives
+    /// the same texture, and it tiles seamlessly. Panics if `size` or `cells` is 0.
+    pub fn value_noise(
+        size: u32,
+        cells: u32,
+        octaves: u32,
+        seed: u32,
+        low: Color,
+        high: Color,
+    ) -> Self {
+        Texture::noise(size, cells, octaves, low, high, |x, y, cells| {
+            value_noise(x, y, cells, seed)
+        })
+    }
 
-        for y in 0..h {
-            for x in 0..w {
-                let pixel = image.get_pixel(x, y);
-                // pixels.push(pixel[0]);
-                // pixels.push(pixel[1]);
-                // pixels.push(pixel[2]);
-                pixels.push(Color(pixel[0], pixel[1], pixel[2]));
+    /// Like [`Texture::value_noise`], with gradient (Perlin) noise, which has less of a
+    /// visible grid
+    pub fn perlin_noise(
+        size: u32,
+        cells: u32,
+        octaves: u32,
+        seed: u32,
+        low: Color,
+        high: Color,
+    ) -> Self {
+        Texture::noise(size, cells, octaves, low, high, |x, y, cells| {
+            perlin_noise(x, y, cells, seed)
+        })
+    }
+
+    /// Fractal noise from `noise(x, y, cells)`, which gives values from 0 to 1 that repeat
+    /// every `cells` in x and y
+    fn noise<F: Fn(f32, f32, u32) -> f32>(
+        size: u32,
+        cells: u32,
+        octaves: u32,
+        low: Color,
+        high: Color,
+        noise: F,
+    ) -> Self {
+        assert!(cells > 0, "Noise needs at least 1 cell");
+        // The last octave has the highest bit of `cells << octave` set
+        let octaves = octaves.clamp(1, cells.leading_zeros() + 1);
+        Texture::from_fn(size, size, |x, y| {
+            let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+            let (mut value, mut total, mut amplitude) = (0.0, 0.0, 1.0);
+            for octave in 0..octaves {
+                let cells = cells << octave;
+                value += noise(u * cells as f32, v * cells as f32, cells) * amplitude;
+                total += amplitude;
+                amplitude *= 0.5;
             }
-        }
+            mix(low, high, value / total)
+        })
+    }
+
+    /// A pattern to check texture coordinates with: red grows with u and green with v, on
+    /// an 8 by 8 checkerboard with lines between the squares. Panics if `size` is 0.
+    pub fn uv_debug(size: u32) -> Self {
+        let cell = (size / 8).max(1);
+        Texture::from_fn(size, size, |x, y| {
+            if x % cell == 0 || y % cell == 0 {
+                return Color::WHITE;
+            }
+            let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+            let shade = match (x / cell + y / cell) % 2 {
+                0 => 1.0,
+                _ => 0.7,
+            };
+            Color::from_vec3_f32s(Vec3(u, v, 0.3) * shade)
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].w
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].h
+    }
+
+    /// Number of mip levels, including the full size image
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Whether the texture has an alpha channel, it may still be fully opaque
+    pub fn has_alpha(&self) -> bool {
+        !self.levels[0].alpha.is_empty()
+    }
+
+    /// The full size image, RGBA if the texture has an alpha channel
+    pub fn to_image(&self) -> DynamicImage {
+        let level = &self.levels[0];
+        let image = match self.has_alpha() {
+            true => {
+                let bytes = (level.pixels.iter().zip(&level.alpha))
+                    .flat_map(|(c, &a)| [c.0, c.1, c.2, a])
+                    .collect();
+                RgbaImage::from_raw(level.w, level.h, bytes).map(DynamicImage::ImageRgba8)
+            }
+            false => {
+                let bytes = level.pixels.iter().flat_map(|c| [c.0, c.1, c.2]).collect();
+                RgbImage::from_raw(level.w, level.h, bytes).map(DynamicImage::ImageRgb8)
+            }
+        };
+        image.expect("Pixel count always matches the texture dimensions")
+    }
+
+    /// Save the texture, picking the image format from the file extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        self.to_image().save(path).map_err(to_string)
+    }
+
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn set_filter(&mut self, filter: FilterMode) {
+        self.filter = filter;
+    }
+
+    pub fn filter(&self) -> FilterMode {
+        self.filter
+    }
+
+    pub fn with_wrap(mut self, wrap_u: WrapMode, wrap_v: WrapMode) -> Self {
+        self.set_wrap(wrap_u, wrap_v);
+        self
+    }
+
+    pub fn set_wrap(&mut self, wrap_u: WrapMode, wrap_v: WrapMode) {
+        self.wrap_u = wrap_u;
+        self.wrap_v = wrap_v;
+    }
 
-        Ok(Texture { pixels, w, h })
+    /// The wrap modes for u and v
+    pub fn wrap(&self) -> (WrapMode, WrapMode) {
+        (self.wrap_u, self.wrap_v)
     }
 
+    /// Sample the full size image at `(u, v)`, where `(0, 0)` is the top left corner of
+    /// the image and `(1, 1)` the bottom right one. Outside of that the texture wraps
+    /// according to [`Texture::wrap`].
     pub fn texel(&self, u: f32, v: f32) -> Color {
-        let u = u.clamp(0.0, 1.0);
-        let v = v.clamp(0.0, 1.0);
+        self.texel_lod(u, v, 0.0)
+    }
 
-        let x = (self.w as f32 * u) as usize;
-        let y = (self.h as f32 * v) as usize;
+    /// Sample the texture at `(u, v)` from mip level `lod`, fractional levels blend the
+    /// two closest ones with [`FilterMode::Bilinear`]. See [`Texture::level_of_detail`].
+    pub fn texel_lod(&self, u: f32, v: f32, lod: f32) -> Color {
+        self.texel_rgba(u, v, lod).0
+    }
+
+    /// [`Texture::texel_lod`] along with the alpha, from 0 (transparent) to 1
+    pub fn texel_rgba(&self, u: f32, v: f32, lod: f32) -> (Color, f32) {
+        let wrap = [self.wrap_u, self.wrap_v];
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+
+        let (color, alpha) = match self.filter {
+            FilterMode::Nearest => {
+                self.levels[lod.round() as usize].sample(u, v, self.filter, wrap)
+            }
+            FilterMode::Bilinear => {
+                let level = lod.floor() as usize;
+                let near = self.levels[level].sample(u, v, self.filter, wrap);
+                let t = lod - level as f32;
+                match self.levels.get(level + 1) {
+                    Some(far) if t > 0.0 => lerp_rgba(near, far.sample(u, v, self.filter, wrap), t),
+                    _ => near,
+                }
+            }
+        };
+        (Color::from_vec3_f32s(color), alpha)
+    }
+
+    /// The mip level to sample from, given how much the uvs change from one pixel to the
+    /// next horizontally (`dx`) and vertically (`dy`). 0 when the texture is magnified.
+    pub fn level_of_detail(&self, dx: Vec2<f32>, dy: Vec2<f32>) -> f32 {
+        let (w, h) = (self.width() as f32, self.height() as f32);
+        // Length of the pixel's footprint in texels, along its longest side
+        let texels = |d: Vec2<f32>| (d.0 * w).hypot(d.1 * h);
+        let footprint = texels(dx).max(texels(dy));
+        if footprint.is_finite() && footprint > 1.0 {
+            footprint.log2()
+        } else {
+            0.0
+        }
+    }
+}
+
+impl MipLevel {
+    /// Texel at `(x, y)` and its alpha, coordinates outside the image wrap around
+    /// according to `wrap`
+    fn pixel(&self, x: i64, y: i64, [wrap_u, wrap_v]: [WrapMode; 2]) -> (Vec3<f32>, f32) {
+        let x = wrap_u.apply(x, self.w);
+        let y = wrap_v.apply(y, self.h);
+        let i = y * self.w as usize + x;
+        let alpha = self.alpha.get(i).map_or(1.0, |&a| a as f32 / 255.0);
+        (self.pixels[i].to_vec3_f32s(), alpha)
+    }
 
-        let x = if x == 0 { x } else { x - 1 };
-        let y = if y == 0 { y } else { y - 1 };
+    fn sample(&self, u: f32, v: f32, filter: FilterMode, wrap: [WrapMode; 2]) -> (Vec3<f32>, f32) {
+        // Texel (x, y) covers x..x + 1 in texture space, its center is at x + 0.5
+        let x = u * self.w as f32;
+        let y = v * self.h as f32;
+
+        match filter {
+            FilterMode::Nearest => self.pixel(x.floor() as i64, y.floor() as i64, wrap),
+            FilterMode::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let pixel = |x, y| self.pixel(x, y, wrap);
+                let top = lerp_rgba(pixel(x0, y0), pixel(x0 + 1, y0), fx);
+                let bottom = lerp_rgba(pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1), fx);
+                lerp_rgba(top, bottom, fy)
+            }
+        }
+    }
 
-        let i = (y * self.w as usize) + x;
-        self.pixels[i]
+    /// The next level, each texel the average of the 2x2 texels it covers. Odd sizes
+    /// round down, a 1 pixel wide or tall side stays as is.
+    fn downsample(&self) -> MipLevel {
+        let (w, h) = ((self.w / 2).max(1), (self.h / 2).max(1));
+        let mut pixels = Vec::with_capacity(w as usize * h as usize);
+        let mut alpha = Vec::with_capacity(self.alpha.len() / 4);
+        for y in 0..h as i64 {
+            for x in 0..w as i64 {
+                let sum = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .map(|(dx, dy)| {
+                        let x = WrapMode::ClampToEdge.apply(x * 2 + dx, self.w);
+                        let y = WrapMode::ClampToEdge.apply(y * 2 + dy, self.h);
+                        let i = y * self.w as usize + x;
+                        let (c, a) = (self.pixels[i], self.alpha.get(i).copied().unwrap_or(255));
+                        [c.0 as u32, c.1 as u32, c.2 as u32, a as u32]
+                    })
+                    .fold([0; 4], |s, c| {
+                        [s[0] + c[0], s[1] + c[1], s[2] + c[2], s[3] + c[3]]
+                    })
+                    .map(|sum| ((sum + 2) / 4) as u8);
+                pixels.push(Color(sum[0], sum[1], sum[2]));
+                if !self.alpha.is_empty() {
+                    alpha.push(sum[3]);
+                }
+            }
+        }
+        MipLevel {
+            pixels,
+            alpha,
+            w,
+            h,
+        }
     }
 }
 
+fn lerp_rgba(
+    (a, a_alpha): (Vec3<f32>, f32),
+    (b, b_alpha): (Vec3<f32>, f32),
+    t: f32,
+) -> (Vec3<f32>, f32) {
+    (&a + (&b - &a) * t, a_alpha + (b_alpha - a_alpha) * t)
+}
+
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    let (a, b) = (a.to_vec3_f32s(), b.to_vec3_f32s());
+    Color::from_vec3_f32s(&a + (&b - &a) * t.clamp(0.0, 1.0))
+}
+
+/// Random number for a lattice point, from 0 to `u32::MAX`
+fn hash(x: u32, y: u32, seed: u32) -> u32 {
+    let mut h = x.wrapping_mul(0x8da6_b343) ^ y.wrapping_mul(0xd816_3841) ^ seed;
+    h = (h ^ (h >> 16)).wrapping_mul(0x7feb_352d);
+    h = (h ^ (h >> 15)).wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+/// The lattice cell containing `(x, y)`, with the lattice repeating every `cells`, and
+/// where in the cell the point is, eased so the noise is smooth across cells
+fn lattice(x: f32, y: f32, cells: u32) -> ([u32; 2], [u32; 2], f32, f32, f32, f32) {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let wrap = |i: f32| (i as i64).rem_euclid(cells as i64) as u32;
+    let (xs, ys) = ([wrap(x0), wrap(x0 + 1.0)], [wrap(y0), wrap(y0 + 1.0)]);
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    (xs, ys, fx, fy, fade(fx), fade(fy))
+}
+
+/// Random values at the lattice points, interpolated in between
+fn value_noise(x: f32, y: f32, cells: u32, seed: u32) -> f32 {
+    let ([x0, x1], [y0, y1], _, _, sx, sy) = lattice(x, y, cells);
+    let value = |x, y| hash(x, y, seed) as f32 / u32::MAX as f32;
+    let top = value(x0, y0) + (value(x1, y0) - value(x0, y0)) * sx;
+    let bottom = value(x0, y1) + (value(x1, y1) - value(x0, y1)) * sx;
+    top + (bottom - top) * sy
+}
+
+/// Random directions at the lattice points, the noise is interpolated from how much the
+/// point is in the direction of each corner
+fn perlin_noise(x: f32, y: f32, cells: u32, seed: u32) -> f32 {
+    let ([x0, x1], [y0, y1], fx, fy, sx, sy) = lattice(x, y, cells);
+    let corner = |x, y, dx: f32, dy: f32| {
+        let angle = hash(x, y, seed) as f32 / u32::MAX as f32 * std::f32::consts::TAU;
+        angle.cos() * dx + angle.sin() * dy
+    };
+    let top = corner(x0, y0, fx, fy) + (corner(x1, y0, fx - 1.0, fy) - corner(x0, y0, fx, fy)) * sx;
+    let bottom = corner(x0, y1, fx, fy - 1.0)
+        + (corner(x1, y1, fx - 1.0, fy - 1.0) - corner(x0, y1, fx, fy - 1.0)) * sx;
+    // From -sqrt(1/2)..sqrt(1/2) to 0..1
+    0.5 + (top + (bottom - top) * sy) * std::f32::consts::FRAC_1_SQRT_2
+}
+
 fn to_string<T: ToString>(t: T) -> String {
     t.to_string()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filtering() {
+        let texture = Texture::from_pixels(vec![Color::BLACK, Color::WHITE], vec![], 2, 1)
+            .with_filter(FilterMode::Nearest)
+            .with_wrap(WrapMode::ClampToEdge, WrapMode::ClampToEdge);
+
+        assert_eq!(texture.texel(0.49, 0.5), Color::BLACK);
+        assert_eq!(texture.texel(0.51, 0.5), Color::WHITE);
+        assert_eq!(texture.texel(1.0, 0.5), Color::WHITE);
+
+        let texture = texture.with_filter(FilterMode::Bilinear);
+        // Texel centers are exact, edges clamp and in between blends
+        assert_eq!(texture.texel(0.25, 0.5), Color::BLACK);
+        assert_eq!(texture.texel(0.0, 0.5), Color::BLACK);
+        assert_eq!(texture.texel(0.75, 1.0), Color::WHITE);
+        assert_eq!(texture.texel(0.5, 0.5), Color(127, 127, 127));
+    }
+
+    #[test]
+    fn mipmaps() {
+        let pixels = (0..16)
+            .map(|i| {
+                if (i + i / 4) % 2 == 0 {
+                    Color::BLACK
+                } else {
+                    Color::WHITE
+                }
+            })
+            .collect();
+        let texture = Texture::from_pixels(pixels, vec![], 4, 4);
+        assert_eq!(texture.level_count(), 3);
+
+        // A checkerboard averages out to gray once the texels are smaller than a pixel
+        assert_eq!(texture.texel_lod(0.125, 0.125, 0.0), Color::BLACK);
+        assert_eq!(texture.texel_lod(0.125, 0.125, 1.0), Color(128, 128, 128));
+        assert_eq!(texture.texel_lod(0.125, 0.125, 0.5), Color(64, 64, 64));
+        assert_eq!(texture.texel_lod(0.125, 0.125, 10.0), Color(128, 128, 128));
+
+        assert_eq!(texture.level_of_detail(Vec2(0.1, 0.0), Vec2(0.0, 0.1)), 0.0);
+        assert_eq!(texture.level_of_detail(Vec2(1.0, 0.0), Vec2(0.0, 0.5)), 2.0);
+    }
+
+    #[test]
+    fn wrapping() {
+        let [r, g, b] = [Color::RED, Color::GREEN, Color::BLUE];
+        let texture =
+            Texture::from_pixels(vec![r, g, b], vec![], 3, 1).with_filter(FilterMode::Nearest);
+
+        let row = |texture: &Texture| [-0.9, -0.1, 1.1, 1.5, 2.9].map(|u| texture.texel(u, 0.5));
+        assert_eq!(row(&texture), [r, b, r, g, b]);
+
+        let texture = texture.with_wrap(WrapMode::MirroredRepeat, WrapMode::Repeat);
+        assert_eq!(row(&texture), [b, r, b, g, b]);
+
+        let texture = texture.with_wrap(WrapMode::ClampToEdge, WrapMode::Repeat);
+        assert_eq!(row(&texture), [r, r, b, b, b]);
+
+        // Bilinear filtering blends across the seam when repeating
+        let texture = texture
+            .with_filter(FilterMode::Bilinear)
+            .with_wrap(WrapMode::Repeat, WrapMode::Repeat);
+        let seam = texture.texel(1.0, 0.5);
+        assert_eq!((seam.0, seam.1), (127, 0));
+    }
+
+    #[test]
+    fn alpha() {
+        let texture = Texture::from_pixels(vec![Color::RED, Color::GREEN], vec![255, 0], 2, 1)
+            .with_wrap(WrapMode::ClampToEdge, WrapMode::ClampToEdge);
+        assert!(texture.has_alpha());
+
+        assert_eq!(texture.texel_rgba(0.25, 0.5, 0.0), (Color::RED, 1.0));
+        assert_eq!(texture.texel_rgba(0.75, 0.5, 0.0), (Color::GREEN, 0.0));
+        assert_eq!(texture.texel_rgba(0.5, 0.5, 0.0).1, 0.5);
+        // The 1x1 level averages the alpha too
+        assert_eq!(texture.texel_rgba(0.5, 0.5, 1.0).1, 128.0 / 255.0);
+
+        let image = texture.to_image();
+        assert_eq!(image.color(), image::ColorType::Rgba8);
+        assert_eq!(Texture::from_image(&image).levels[0].alpha, vec![255, 0]);
+    }
+
+    #[test]
+    fn procedural() {
+        let (a, b) = (Color::RED, Color::BLUE);
+        let checkers = Texture::checkerboard(4, 16, a, b);
+        assert_eq!((checkers.width(), checkers.height()), (64, 64));
+        let nearest = checkers.clone().with_filter(FilterMode::Nearest);
+        assert_eq!(nearest.texel(0.1, 0.1), a);
+        assert_eq!(nearest.texel(0.3, 0.1), b);
+        assert_eq!(nearest.texel(0.3, 0.3), a);
+
+        let grid = Texture::grid(2, 4, 1, Color::WHITE, Color::BLACK);
+        assert_eq!(grid.levels[0].pixels[..5], [Color::WHITE; 5]);
+        assert_eq!(grid.levels[0].pixels[9], Color::BLACK);
+
+        let gradient = Texture::gradient(2, 1, Gradient::Horizontal, Color::BLACK, Color::WHITE);
+        assert_eq!(
+            gradient.levels[0].pixels,
+            [Color(63, 63, 63), Color(191, 191, 191)]
+        );
+
+        for noise in [Texture::value_noise, Texture::perlin_noise] {
+            let texture = noise(32, 4, 3, 7, Color::BLACK, Color::WHITE);
+            let pixels = &texture.levels[0].pixels;
+            assert_eq!(
+                *pixels,
+                noise(32, 4, 3, 7, Color::BLACK, Color::WHITE).levels[0].pixels
+            );
+            assert_ne!(
+                *pixels,
+                noise(32, 4, 3, 8, Color::BLACK, Color::WHITE).levels[0].pixels
+            );
+            // Smooth, and seamless when repeated
+            for y in 0..32 {
+                let (first, last) = (pixels[y * 32], pixels[y * 32 + 31]);
+                assert!((first.0 as i32 - last.0 as i32).abs() < 40);
+            }
+            // More octaves than fit, and the most cells there are
+            noise(4, 1, u32::MAX, 7, Color::BLACK, Color::WHITE);
+            noise(4, u32::MAX, 3, 7, Color::BLACK, Color::WHITE);
+        }
+
+        let uv = Texture::uv_debug(64).with_filter(FilterMode::Nearest);
+        let (top_right, bottom_left) = (uv.texel(0.95, 0.05), uv.texel(0.05, 0.95));
+        assert!(top_right.0 > 150 && top_right.1 < 50);
+        assert!(bottom_left.0 < 50 && bottom_left.1 > 150);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1x1")]
+    fn empty() {
+        Texture::gradient(0, 4, Gradient::Vertical, Color::BLACK, Color::WHITE);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1 cell")]
+    fn no_cells() {
+        Texture::perlin_noise(4, 0, 1, 7, Color::BLACK, Color::WHITE);
+    }
+}
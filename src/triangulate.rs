@@ -0,0 +1,132 @@
+use crate::math::Vec3;
+
+/// Split a planar polygon into triangles, returned as indices into `points`. Convex
+/// polygons are split into a fan around the first point, concave ones are split by ear
+/// clipping. Both keep the winding of the polygon.
+pub fn triangulate(points: &[Vec3<f32>]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return vec![];
+    }
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    let normal = newell_normal(points);
+    if is_convex(points, &normal) {
+        return fan(&(0..points.len()).collect::<Vec<_>>());
+    }
+    ear_clip(points, &normal)
+}
+
+/// Normal of a polygon that works for concave polygons and isn't thrown off by
+/// collinear points, its direction follows the winding of the polygon
+fn newell_normal(points: &[Vec3<f32>]) -> Vec3<f32> {
+    let mut normal = Vec3(0.0, 0.0, 0.0);
+    for (i, current) in points.iter().enumerate() {
+        let next = &points[(i + 1) % points.len()];
+        normal.0 += (current.1 - next.1) * (current.2 + next.2);
+        normal.1 += (current.2 - next.2) * (current.0 + next.0);
+        normal.2 += (current.0 - next.0) * (current.1 + next.1);
+    }
+    normal
+}
+
+/// Whether the corner `b` (coming from `a`, going to `c`) turns the same way as the
+/// polygon's winding. Straight corners count as convex.
+fn is_convex_corner(a: &Vec3<f32>, b: &Vec3<f32>, c: &Vec3<f32>, normal: &Vec3<f32>) -> bool {
+    (b - a).cross(&(c - b)).dot(normal) >= 0.0
+}
+
+fn is_convex(points: &[Vec3<f32>], normal: &Vec3<f32>) -> bool {
+    let n = points.len();
+    (0..n).all(|i| {
+        is_convex_corner(
+            &points[(i + n - 1) % n],
+            &points[i],
+            &points[(i + 1) % n],
+            normal,
+        )
+    })
+}
+
+fn fan(polygon: &[usize]) -> Vec<[usize; 3]> {
+    (1..polygon.len() - 1)
+        .map(|i| [polygon[0], polygon[i], polygon[i + 1]])
+        .collect()
+}
+
+fn ear_clip(points: &[Vec3<f32>], normal: &Vec3<f32>) -> Vec<[usize; 3]> {
+    let mut polygon: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+
+    while polygon.len() > 3 {
+        let n = polygon.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+            is_convex_corner(&points[a], &points[b], &points[c], normal)
+                && !polygon
+                    .iter()
+                    .filter(|&&p| p != a && p != b && p != c)
+                    .any(|&p| {
+                        is_in_triangle(&points[p], [&points[a], &points[b], &points[c]], normal)
+                    })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]]);
+                polygon.remove(i);
+            }
+            // Self intersecting or otherwise broken polygon, fall back to a fan so that
+            // at least something gets drawn
+            None => break,
+        }
+    }
+
+    triangles.extend(fan(&polygon));
+    triangles
+}
+
+fn is_in_triangle(p: &Vec3<f32>, [a, b, c]: [&Vec3<f32>; 3], normal: &Vec3<f32>) -> bool {
+    is_convex_corner(a, b, p, normal)
+        && is_convex_corner(b, c, p, normal)
+        && is_convex_corner(c, a, p, normal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quad_is_a_fan() {
+        let quad = [
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(1.0, 0.0, 0.0),
+            Vec3(1.0, 1.0, 0.0),
+            Vec3(0.0, 1.0, 0.0),
+        ];
+
+        assert_eq!(triangulate(&quad), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn concave() {
+        // Arrow pointing right with the notch at index 3, a fan around 0 would cover it
+        let arrow = [
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(2.0, 1.0, 0.0),
+            Vec3(0.0, 2.0, 0.0),
+            Vec3(0.5, 1.0, 0.0),
+        ];
+        let triangles = triangulate(&arrow);
+
+        assert_eq!(triangles.len(), 2);
+        for [a, b, c] in triangles {
+            // Every triangle keeps the counter clockwise winding
+            let normal = (&arrow[b] - &arrow[a]).cross(&(&arrow[c] - &arrow[a]));
+            assert!(normal.2 > 0.0);
+            // and none of them spans the notch
+            assert!([a, b, c].contains(&3));
+        }
+    }
+}
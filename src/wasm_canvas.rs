@@ -50,6 +50,17 @@ impl Canvas for WasmCanvas {
         }
     }
 
+    fn get_pixel<X: crate::canvas::IntoPixelValue, Y: crate::canvas::IntoPixelValue>(
+        &self,
+        x: X,
+        y: Y,
+    ) -> Option<crate::rasterize::Color> {
+        let (x, y) = canvas_coords_to_screen_coords(x, y, self.width, self.height)?;
+        let data = &self.canvas_buffer;
+        let i: usize = (y as usize * self.width as usize * 4) + (x as usize * 4);
+        Some(crate::rasterize::Color(data[i], data[i + 1], data[i + 2]))
+    }
+
     fn clear(&mut self, color: crate::rasterize::Color) {
         let mut i = 0;
         while i < self.canvas_buffer.len() {
@@ -1,15 +1,80 @@
 use std::{
+    collections::HashMap,
     io::{BufRead, Read},
-    path::Path,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
 use crate::{
-    math::{Vec2, Vec3},
-    object::Triangle,
+    math::{Radians, Vec2, Vec3},
+    normals::{position_key, smooth_normals, DEFAULT_CREASE_ANGLE},
+    object::{Part, Triangle},
     rasterize::Color,
+    texture::Texture,
+    triangulate::triangulate,
 };
 
+/// Error while loading an obj or mtl file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    /// 1-based line number, 0 when the error isn't about a specific line
+    pub line: usize,
+    /// The word or line that couldn't be read
+    pub token: String,
+    pub kind: ObjErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjErrorKind {
+    /// The file couldn't be read
+    Io(String),
+    InvalidNumber,
+    /// A line ended before all the expected numbers were read
+    MissingComponent,
+    /// A face refers to a vertex, texture coordinate or normal that doesn't exist
+    IndexOutOfRange,
+    /// Texture coordinates were requested but a face has none
+    MissingUv,
+    /// A face with less than three vertices, or a vertex with more than three indices
+    InvalidFace,
+}
+
+impl ObjError {
+    fn new(line: usize, token: &str, kind: ObjErrorKind) -> Self {
+        Self {
+            line,
+            token: token.to_string(),
+            kind,
+        }
+    }
+
+    fn io<E: ToString>(path: &Path, e: E) -> Self {
+        Self::new(0, &path.to_string_lossy(), ObjErrorKind::Io(e.to_string()))
+    }
+}
+
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match &self.kind {
+            ObjErrorKind::Io(e) => return write!(f, "Couldn't read {}: {}", self.token, e),
+            ObjErrorKind::InvalidNumber => "invalid number",
+            ObjErrorKind::MissingComponent => "missing component",
+            ObjErrorKind::IndexOutOfRange => "index out of range",
+            ObjErrorKind::MissingUv => "missing texture coordinate",
+            ObjErrorKind::InvalidFace => "invalid face",
+        };
+        write!(f, "Line {}: {} `{}`", self.line, reason, self.token)
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<ObjError> for String {
+    fn from(e: ObjError) -> Self {
+        e.to_string()
+    }
+}
+
 /// Face can be of the following forms:
 ///
 /// Only vertex indices:
@@ -20,163 +85,721 @@ use crate::{
 ///
 /// Vertex indices and texture coordinate indices and vertex normal indices:
 /// f v1/vt1/vn1 v2/vt2/vn2 v3/vt3/vn3
+///
+/// Faces can have any number of corners (at least three), they're triangulated when the
+/// triangles are made.
 #[derive(Debug, Clone)]
-struct Face(Vec3<(usize, Option<usize>, Option<usize>)>);
+struct Face {
+    /// Vertex, texture coordinate and normal index of each corner
+    corners: Vec<(usize, Option<usize>, Option<usize>)>,
+    /// Index into [`WavefrontObj::used_materials`], set by the last `usemtl`
+    material: Option<usize>,
+    /// Line the face was on, for errors about its indices
+    line: usize,
+    /// Index into [`WavefrontObj::parts`], set by the last `o` and `g`
+    part: usize,
+    /// Set by the last `s`, 0 when smoothing is off. Faces in the same group share
+    /// vertex normals, other faces don't.
+    smoothing_group: u32,
+}
 
 impl Face {
-    fn parse<'a, I: Iterator<Item = &'a str>>(words: I) -> Self {
+    /// `counts` are the number of vertices, texture coordinates and normals read so far,
+    /// negative indices count back from the last of them (-1 is the last one).
+    fn parse<'a, I: Iterator<Item = &'a str>>(
+        words: I,
+        material: Option<usize>,
+        line: usize,
+        counts: (usize, usize, usize),
+    ) -> Result<Self, ObjError> {
         let mut face_vertices = vec![];
         for w in words {
-            let vi: usize;
-            let mut vti = None;
-            let mut vni = None;
-            match w.chars().filter(|c| *c == '/').count() {
-                0 => {
-                    vi = w.parse::<usize>().unwrap();
+            let index = |component: &str, count: usize| {
+                let index = component
+                    .parse::<isize>()
+                    .map_err(|_| ObjError::new(line, w, ObjErrorKind::InvalidNumber))?;
+                if index >= 0 {
+                    Ok(index as usize)
+                } else {
+                    // Resolve to the 1-based index
+                    count
+                        .checked_sub(index.unsigned_abs() - 1)
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| ObjError::new(line, w, ObjErrorKind::IndexOutOfRange))
                 }
-                1 => {
-                    let mut components = w.split("/").map(|w| w.parse::<usize>().unwrap());
-                    vi = components.next().unwrap();
-                    vti = Some(components.next().unwrap());
+            };
+            // For some reason faces like this are allowed: f 420//69
+            let optional_index = |component: Option<&str>, count: usize| match component {
+                None | Some("") => Ok(None),
+                Some(component) => index(component, count).map(Some),
+            };
+
+            let mut components = w.split('/');
+            let vi = index(components.next().unwrap_or_default(), counts.0)?;
+            let vti = optional_index(components.next(), counts.1)?;
+            let vni = optional_index(components.next(), counts.2)?;
+            if components.next().is_some() {
+                return Err(ObjError::new(line, w, ObjErrorKind::InvalidFace));
+            }
+            face_vertices.push((vi, vti, vni))
+        }
+
+        if face_vertices.len() < 3 {
+            let token = face_vertices.len().to_string() + " vertices";
+            return Err(ObjError::new(line, &token, ObjErrorKind::InvalidFace));
+        }
+
+        Ok(Face {
+            corners: face_vertices,
+            material,
+            line,
+            part: 0,
+            smoothing_group: 0,
+        })
+    }
+}
+
+/// A material from a `.mtl` material library
+#[derive(Debug, Clone)]
+pub struct WavefrontMaterial {
+    pub name: String,
+    /// `Ka`
+    pub ambient: Vec3<f32>,
+    /// `Kd`
+    pub diffuse: Vec3<f32>,
+    /// `Ks`
+    pub specular: Vec3<f32>,
+    /// `Ns`
+    pub shininess: f32,
+    /// `d`, 1 is opaque. `Tr` is read as `1 - d`.
+    pub dissolve: f32,
+    /// `map_Kd`, relative paths are resolved against the library's directory
+    pub diffuse_map: Option<PathBuf>,
+    /// `map_Bump` or `bump`. Only parsed, the rasterizer doesn't do bump mapping
+    pub bump_map: Option<PathBuf>,
+}
+
+impl WavefrontMaterial {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ambient: Vec3(1.0, 1.0, 1.0),
+            diffuse: Vec3(1.0, 1.0, 1.0),
+            specular: Vec3(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            dissolve: 1.0,
+            diffuse_map: None,
+            bump_map: None,
+        }
+    }
+
+    /// Parse all the materials in a material library. Texture paths are resolved
+    /// against `dir`, which should be the directory of the library.
+    pub fn parse_library<R: Read>(r: R, dir: &Path) -> Result<Vec<WavefrontMaterial>, ObjError> {
+        let mut materials: Vec<WavefrontMaterial> = vec![];
+        for (i, l) in std::io::BufReader::new(r).lines().enumerate() {
+            let line_number = i + 1;
+            let line =
+                l.map_err(|e| ObjError::new(line_number, "", ObjErrorKind::Io(e.to_string())))?;
+            let mut words = line.split_whitespace();
+
+            let keyword = words.next();
+            if keyword == Some("newmtl") {
+                materials.push(WavefrontMaterial::new(&words.collect::<Vec<_>>().join(" ")));
+                continue;
+            }
+            let material = match materials.last_mut() {
+                Some(material) => material,
+                None => continue,
+            };
+            match keyword {
+                Some("Ka") => material.ambient = parse_vec3(words, line_number, &line)?,
+                Some("Kd") => material.diffuse = parse_vec3(words, line_number, &line)?,
+                Some("Ks") => material.specular = parse_vec3(words, line_number, &line)?,
+                Some("Ns") => material.shininess = parse_number(words.next(), line_number, &line)?,
+                Some("d") => material.dissolve = parse_number(words.next(), line_number, &line)?,
+                Some("Tr") => {
+                    material.dissolve = 1.0 - parse_number::<f32>(words.next(), line_number, &line)?
                 }
-                2 => {
-                    let mut components = w.split("/").map(|w| w.parse::<usize>());
-                    vi = components.next().unwrap().unwrap();
-                    // For some reason faces like this are allowed: f 420//69
-                    vti = components.next().unwrap().map(Some).unwrap_or(None);
-                    vni = components.next().unwrap().map(Some).unwrap_or(None);
+                Some("map_Kd") => material.diffuse_map = Self::parse_map(words, dir),
+                Some("map_Bump") | Some("map_bump") | Some("bump") => {
+                    material.bump_map = Self::parse_map(words, dir)
                 }
-                _ => panic!("Invalid word: {}", w),
+                Some(_) => continue,
+                None => continue,
             }
-            face_vertices.push((vi, vti, vni))
         }
+        Ok(materials)
+    }
 
-        Face(Vec3(
-            face_vertices[0].into(),
-            face_vertices[1].into(),
-            face_vertices[2].into(),
-        ))
+    /// Maps can have options like `-bm 1.0` before the file name, which is always last
+    fn parse_map<'a, I: Iterator<Item = &'a str>>(words: I, dir: &Path) -> Option<PathBuf> {
+        words.last().map(|file| dir.join(file.replace('\\', "/")))
+    }
+
+    /// Color to tint the surface with
+    pub fn diffuse_color(&self) -> Color {
+        Color::from_vec3_f32s(self.diffuse.clone())
+    }
+
+    pub fn specular_color(&self) -> Color {
+        Color::from_vec3_f32s(self.specular.clone())
     }
 }
 
-// TODO: Materials, normal, textures, etc.
 #[derive(Debug, Clone)]
 pub struct WavefrontObj {
     vertices: Vec<Vec3<f32>>,
     vertex_texture_indices: Vec<Vec2<f32>>,
     vertex_normal_indices: Vec<Vec3<f32>>,
     faces: Vec<Face>,
+    /// Files referenced by `mtllib`
+    material_libraries: Vec<String>,
+    /// Names referenced by `usemtl`, in the order they first appear
+    used_materials: Vec<String>,
+    materials: Vec<WavefrontMaterial>,
+    /// Object (`o`) and group (`g`) names, in the order they first appear
+    parts: Vec<(Option<String>, Option<String>)>,
+    /// Whether the file has any `s` statements
+    has_smoothing_groups: bool,
+    crease_angle: Radians,
 }
 
+/// Position and smoothing group of a corner, corners with the same key share their
+/// generated normal. The position is used rather than the vertex index since files often
+/// have duplicate vertices along seams. The group is the `s` group and the line of the
+/// face for faces that aren't smoothed.
+type CornerKey = ([u32; 3], (u32, usize));
+
 impl WavefrontObj {
-    pub fn from_file<P: AsRef<Path>>(p: P, scale_coords: f32) -> Self {
-        Self::from_reader(std::fs::File::open(p).unwrap(), scale_coords)
+    /// Load an obj file and the material libraries it references. Missing libraries are
+    /// logged and skipped, the faces using their materials get the default material.
+    pub fn from_file<P: AsRef<Path>>(p: P, scale_coords: f32) -> Result<Self, ObjError> {
+        let p = p.as_ref();
+        let file = std::fs::File::open(p).map_err(|e| ObjError::io(p, e))?;
+        let mut obj = Self::from_reader(file, scale_coords)?;
+
+        let dir = p.parent().unwrap_or_else(|| Path::new(""));
+        for library in obj.material_libraries.clone() {
+            let path = dir.join(&library);
+            match std::fs::File::open(&path) {
+                Ok(file) => {
+                    let library_dir = path.parent().unwrap_or(dir);
+                    obj.add_materials(WavefrontMaterial::parse_library(file, library_dir)?);
+                }
+                Err(e) => log::warn!("Couldn't open material library {:?}: {}", path, e),
+            }
+        }
+
+        Ok(obj)
     }
 
-    pub fn from_reader<R: Read>(r: R, scale_coords: f32) -> Self {
+    /// Parse an obj file. Material libraries aren't loaded since there's no directory to
+    /// look for them in, see [`Self::material_libraries`] and [`Self::add_materials`].
+    pub fn from_reader<R: Read>(r: R, scale_coords: f32) -> Result<Self, ObjError> {
         let mut vertices = vec![];
         let mut vertex_texture_indices = vec![];
         let mut vertex_normal_indices = vec![];
         let mut faces = vec![];
-        for l in std::io::BufReader::new(r).lines() {
-            let line = l.unwrap();
-            let mut words = line.split(" ").filter(|s| s.len() != 0);
+        let mut material_libraries = vec![];
+        let mut used_materials: Vec<String> = vec![];
+        let mut material = None;
+        let mut parts = vec![(None, None)];
+        let mut object: Option<String> = None;
+        let mut group: Option<String> = None;
+        let mut smoothing_group = 0;
+        let mut has_smoothing_groups = false;
+        for (i, l) in std::io::BufReader::new(r).lines().enumerate() {
+            let line_number = i + 1;
+            let line =
+                l.map_err(|e| ObjError::new(line_number, "", ObjErrorKind::Io(e.to_string())))?;
+            let mut words = line.split_whitespace();
 
             match words.nth(0) {
                 Some("#") => continue,
                 Some("v") => {
-                    vertices.push(Self::parse_vec3(words) * scale_coords);
+                    vertices.push(parse_vec3(words, line_number, &line)? * scale_coords);
                     // println!("Last {:?}", vertices.last().as_ref().unwrap())
                 }
                 Some("vt") => {
-                    vertex_texture_indices.push(Self::parse_vec2(words));
+                    vertex_texture_indices.push(parse_vec2(words, line_number, &line)?);
                 }
                 Some("vn") => {
-                    vertex_normal_indices.push(Self::parse_vec3(words));
+                    vertex_normal_indices.push(parse_vec3(words, line_number, &line)?);
                 }
                 Some("f") => {
-                    faces.push(Face::parse(words));
+                    let counts = (
+                        vertices.len(),
+                        vertex_texture_indices.len(),
+                        vertex_normal_indices.len(),
+                    );
+                    let mut face = Face::parse(words, material, line_number, counts)?;
+                    let name = (object.clone(), group.clone());
+                    face.part = match parts.iter().position(|part| *part == name) {
+                        Some(i) => i,
+                        None => {
+                            parts.push(name);
+                            parts.len() - 1
+                        }
+                    };
+                    face.smoothing_group = smoothing_group;
+                    faces.push(face);
+                }
+                Some("o") => {
+                    object = Some(words.collect::<Vec<_>>().join(" "));
+                    // Groups don't carry over to the next object
+                    group = None;
+                }
+                Some("g") => {
+                    let names = words.collect::<Vec<_>>().join(" ");
+                    group = if names.is_empty() { None } else { Some(names) };
+                }
+                Some("s") => {
+                    has_smoothing_groups = true;
+                    smoothing_group = match words.next() {
+                        Some("off") | None => 0,
+                        Some(word) => word.parse().map_err(|_| {
+                            ObjError::new(line_number, word, ObjErrorKind::InvalidNumber)
+                        })?,
+                    };
+                }
+                Some("mtllib") => {
+                    material_libraries.extend(words.map(|w| w.to_string()));
+                }
+                Some("usemtl") => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    material = Some(match used_materials.iter().position(|used| *used == name) {
+                        Some(i) => i,
+                        None => {
+                            used_materials.push(name);
+                            used_materials.len() - 1
+                        }
+                    });
                 }
                 Some(_) => continue,
                 None => continue,
             }
         }
 
-        Self {
+        Ok(Self {
             vertices,
             vertex_texture_indices,
             vertex_normal_indices,
             faces,
+            material_libraries,
+            used_materials,
+            materials: vec![],
+            parts,
+            has_smoothing_groups,
+            crease_angle: DEFAULT_CREASE_ANGLE,
+        })
+    }
+
+    /// Angle between faces above which generated normals aren't smoothed, so the edge
+    /// stays sharp. Only used when the file has no smoothing groups.
+    pub fn with_crease_angle<R: Into<Radians>>(mut self, angle: R) -> Self {
+        self.crease_angle = angle.into();
+        self
+    }
+
+    /// Name of every part of the obj, the `g` name or the `o` name if there's no group
+    pub fn part_names(&self) -> Vec<String> {
+        self.parts
+            .iter()
+            .map(|(object, group)| {
+                group
+                    .clone()
+                    .or_else(|| object.clone())
+                    .unwrap_or_else(|| "default".to_string())
+            })
+            .collect()
+    }
+
+    /// Files referenced by `mtllib` lines, relative to the obj file
+    pub fn material_libraries(&self) -> &[String] {
+        &self.material_libraries
+    }
+
+    /// Add materials from a material library, they're matched to faces by name
+    pub fn add_materials(&mut self, materials: Vec<WavefrontMaterial>) {
+        self.materials.extend(materials);
+    }
+
+    pub fn materials(&self) -> &[WavefrontMaterial] {
+        &self.materials
+    }
+
+    /// Load the diffuse maps of the materials, keyed by path. Textures that fail to load
+    /// are logged and left out, the materials using them are drawn untextured.
+    pub fn load_textures(&self) -> HashMap<PathBuf, Texture> {
+        let mut textures = HashMap::new();
+        for path in self.materials.iter().filter_map(|m| m.diffuse_map.as_ref()) {
+            if textures.contains_key(path) {
+                continue;
+            }
+            match Texture::load(&path.to_string_lossy()) {
+                Ok(texture) => {
+                    textures.insert(path.clone(), texture);
+                }
+                Err(e) => log::warn!("Couldn't load texture {:?}: {}", path, e),
+            }
         }
+        textures
+    }
+
+    /// Index into [`Self::materials`] of the material used by a face
+    fn face_material(&self, face: &Face) -> Option<usize> {
+        let name = &self.used_materials[face.material?];
+        self.materials.iter().position(|m| m.name == *name)
+    }
+
+    pub fn has_normals(&self) -> bool {
+        !self.vertex_normal_indices.is_empty()
     }
 
-    fn parse_vec3<'a, T, I>(words: I) -> Vec3<T>
-    where
-        T: FromStr,
-        <T as FromStr>::Err: std::fmt::Debug,
-        I: Iterator<Item = &'a str>,
-    {
-        let mut parsed = words.map(|word| word.parse::<T>().unwrap());
-        Vec3(
-            parsed.next().unwrap(),
-            parsed.next().unwrap(),
-            parsed.next().unwrap(),
-        )
+    pub fn has_uvs(&self) -> bool {
+        !self.vertex_texture_indices.is_empty()
     }
+}
 
-    fn parse_vec2<'a, T, I>(words: I) -> Vec2<T>
-    where
-        T: FromStr,
-        <T as FromStr>::Err: std::fmt::Debug,
-        I: Iterator<Item = &'a str>,
-    {
-        let mut parsed = words.map(|word| word.parse::<T>().unwrap());
-        Vec2(parsed.next().unwrap(), parsed.next().unwrap())
+fn parse_number<T: FromStr>(
+    word: Option<&str>,
+    line_number: usize,
+    line: &str,
+) -> Result<T, ObjError> {
+    match word {
+        Some(word) => word
+            .parse::<T>()
+            .map_err(|_| ObjError::new(line_number, word, ObjErrorKind::InvalidNumber)),
+        None => Err(ObjError::new(
+            line_number,
+            line.trim(),
+            ObjErrorKind::MissingComponent,
+        )),
     }
 }
 
+fn parse_vec3<'a, T, I>(mut words: I, line_number: usize, line: &str) -> Result<Vec3<T>, ObjError>
+where
+    T: FromStr,
+    I: Iterator<Item = &'a str>,
+{
+    Ok(Vec3(
+        parse_number(words.next(), line_number, line)?,
+        parse_number(words.next(), line_number, line)?,
+        parse_number(words.next(), line_number, line)?,
+    ))
+}
+
+fn parse_vec2<'a, T, I>(mut words: I, line_number: usize, line: &str) -> Result<Vec2<T>, ObjError>
+where
+    T: FromStr,
+    I: Iterator<Item = &'a str>,
+{
+    Ok(Vec2(
+        parse_number(words.next(), line_number, line)?,
+        parse_number(words.next(), line_number, line)?,
+    ))
+}
+
 impl WavefrontObj {
+    /// Fails if a face refers to something that doesn't exist, or when `textured` is set
+    /// and a face is missing texture coordinates. When `normals` is set, faces without
+    /// normals get generated ones, see [`Self::with_crease_angle`].
     pub fn make_triangles(
         &self,
         color: Option<Color>,
         textured: bool,
         normals: bool,
         outlines: bool,
-    ) -> Vec<Triangle> {
+    ) -> Result<Vec<Triangle>, ObjError> {
         let mut triangles = vec![];
+        for face in &self.faces {
+            self.push_face_triangles(&mut triangles, face, color, textured, normals, outlines)?;
+        }
+        Ok(self.fill_missing_normals(triangles, normals))
+    }
 
-        for Face(indices) in &self.faces {
-            triangles.push(Triangle {
-                p0: self.vertices[indices.0 .0 - 1].clone(),
-                p1: self.vertices[if !outlines {
-                    indices.1 .0 - 1
-                } else {
-                    indices.0 .0 - 1
-                }]
-                .clone(),
-                p2: self.vertices[indices.2 .0 - 1].clone(),
+    /// Like [`Self::make_triangles`], but with the triangles ordered by part, with the
+    /// range of each part. Parts without faces are left out.
+    pub fn make_parts(
+        &self,
+        color: Option<Color>,
+        textured: bool,
+        normals: bool,
+        outlines: bool,
+    ) -> Result<(Vec<Triangle>, Vec<Part>), ObjError> {
+        let mut faces_by_part = vec![vec![]; self.parts.len()];
+        for face in &self.faces {
+            faces_by_part[face.part].push(face);
+        }
+
+        let mut triangles = vec![];
+        let mut parts = vec![];
+        for (name, faces) in self.part_names().into_iter().zip(faces_by_part) {
+            if faces.is_empty() {
+                continue;
+            }
+            let start = triangles.len();
+            for face in faces {
+                self.push_face_triangles(&mut triangles, face, color, textured, normals, outlines)?;
+            }
+            parts.push(Part {
+                name,
+                triangles: start..triangles.len(),
+            });
+        }
+        Ok((self.fill_missing_normals(triangles, normals), parts))
+    }
+
+    /// Generate the normals of the triangles that were made without them. Corners of
+    /// the same vertex are smoothed together when they're in the same smoothing group
+    /// or, if the file has no smoothing groups, when the angle between their faces is
+    /// below the crease angle. Nothing is generated when `normals` isn't set.
+    fn fill_missing_normals(
+        &self,
+        triangles: Vec<(Triangle, [CornerKey; 3])>,
+        normals: bool,
+    ) -> Vec<Triangle> {
+        let (mut triangles, keys): (Vec<_>, Vec<_>) = triangles.into_iter().unzip();
+        if !normals || triangles.iter().all(|t| t.normals.is_some()) {
+            return triangles;
+        }
+
+        let crease_angle = if self.has_smoothing_groups {
+            Radians::new(std::f32::consts::PI)
+        } else {
+            self.crease_angle
+        };
+        let generated = smooth_normals(&triangles, &keys, crease_angle);
+        for (t, normals) in triangles.iter_mut().zip(generated) {
+            if t.normals.is_none() {
+                t.normals = Some(normals);
+            }
+        }
+        triangles
+    }
+
+    fn push_face_triangles(
+        &self,
+        triangles: &mut Vec<(Triangle, [CornerKey; 3])>,
+        face: &Face,
+        color: Option<Color>,
+        textured: bool,
+        normals: bool,
+        outlines: bool,
+    ) -> Result<(), ObjError> {
+        let points = face
+            .corners
+            .iter()
+            .map(|(vi, _, _)| {
+                Self::lookup(&self.vertices, Some(*vi), face, ObjErrorKind::InvalidFace)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Faces that aren't smoothed (`s off`) don't share normals with any other face
+        let smoothing = match face.smoothing_group {
+            0 if self.has_smoothing_groups => (0, face.line),
+            group => (group, 0),
+        };
+
+        for [c0, c1, c2] in triangulate(&points) {
+            let corners = [&face.corners[c0], &face.corners[c1], &face.corners[c2]];
+            let keys = [c0, c1, c2].map(|c| (position_key(&points[c]), smoothing));
+            let triangle = Triangle {
+                p0: points[c0].clone(),
+                p1: points[if !outlines { c1 } else { c0 }].clone(),
+                p2: points[c2].clone(),
                 color: color.unwrap_or_default(),
-                normals: if normals {
-                    Some([
-                        self.vertex_normal_indices[indices.0 .2.unwrap() - 1].clone() * 1.0,
-                        self.vertex_normal_indices[indices.1 .2.unwrap() - 1].clone() * 1.0,
-                        self.vertex_normal_indices[indices.2 .2.unwrap() - 1].clone() * 1.0,
-                    ])
+                colors: None,
+                material: self.face_material(face),
+                // Generated later if the face doesn't have normals
+                normals: if normals && corners.iter().all(|(_, _, vni)| vni.is_some()) {
+                    let [n0, n1, n2] = corners.map(|(_, _, vni)| {
+                        Self::lookup(
+                            &self.vertex_normal_indices,
+                            *vni,
+                            face,
+                            ObjErrorKind::IndexOutOfRange,
+                        )
+                    });
+                    Some([n0?, n1?, n2?])
                 } else {
                     None
                 },
                 uvs: if textured {
-                    Some([
-                        self.vertex_texture_indices[indices.1 .1.unwrap() - 1].clone(),
-                        self.vertex_texture_indices[indices.2 .1.unwrap() - 1].clone(),
-                        self.vertex_texture_indices[indices.0 .1.unwrap() - 1].clone(),
-                    ])
+                    let [uv0, uv1, uv2] = corners.map(|(_, vti, _)| {
+                        Self::lookup(
+                            &self.vertex_texture_indices,
+                            *vti,
+                            face,
+                            ObjErrorKind::MissingUv,
+                        )
+                    });
+                    Some([uv0?, uv1?, uv2?])
                 } else {
                     None
                 },
+            };
+            triangles.push((triangle, keys));
+        }
+
+        Ok(())
+    }
+
+    /// Look up a 1-based index of a face, `missing` is the error when the face doesn't
+    /// have the index at all
+    fn lookup<T: Clone>(
+        items: &[T],
+        index: Option<usize>,
+        face: &Face,
+        missing: ObjErrorKind,
+    ) -> Result<T, ObjError> {
+        let index = index.ok_or_else(|| ObjError::new(face.line, "f", missing))?;
+        index
+            .checked_sub(1)
+            .and_then(|i| items.get(i))
+            .cloned()
+            .ok_or_else(|| {
+                ObjError::new(face.line, &index.to_string(), ObjErrorKind::IndexOutOfRange)
             })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::Degrees;
+
+    #[test]
+    fn materials() {
+        let mtl =
+            "newmtl red\nKd 1 0 0\nNs 80\nd 0.5\n\nnewmtl textured\n\tmap_Kd -s 1 1 1 tex.png\n";
+        let obj =
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl textured\nf 1 2 3\nusemtl red\nf 1 2 3\n";
+
+        let mut obj = WavefrontObj::from_reader(obj.as_bytes(), 1.0).unwrap();
+        obj.add_materials(
+            WavefrontMaterial::parse_library(mtl.as_bytes(), Path::new("models")).unwrap(),
+        );
+
+        let red = &obj.materials()[0];
+        assert_eq!(red.diffuse_color(), Color(255, 0, 0));
+        assert_eq!(red.shininess, 80.0);
+        assert_eq!(red.dissolve, 0.5);
+        assert_eq!(
+            obj.materials()[1].diffuse_map,
+            Some(PathBuf::from("models/tex.png"))
+        );
+
+        let triangles = obj.make_triangles(None, false, false, false).unwrap();
+        let materials: Vec<_> = triangles.iter().map(|t| t.material).collect();
+        assert_eq!(materials, vec![None, Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn quads() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n";
+        let obj = WavefrontObj::from_reader(obj.as_bytes(), 1.0).unwrap();
+        let triangles = obj.make_triangles(None, true, false, false).unwrap();
+
+        assert_eq!(triangles.len(), 2);
+        for t in &triangles {
+            // Each corner keeps its own texture coordinates
+            let uvs = t.uvs.as_ref().unwrap();
+            assert_eq!((t.p0.0, t.p0.1), (uvs[0].0, uvs[0].1));
+            assert_eq!((t.p1.0, t.p1.1), (uvs[1].0, uvs[1].1));
+            assert_eq!((t.p2.0, t.p2.1), (uvs[2].0, uvs[2].1));
         }
+    }
 
-        triangles
+    #[test]
+    fn errors() {
+        let error = |obj: &str| WavefrontObj::from_reader(obj.as_bytes(), 1.0).unwrap_err();
+
+        assert_eq!(
+            error("v 0 0 0\nv 1 x 0\n"),
+            ObjError::new(2, "x", ObjErrorKind::InvalidNumber)
+        );
+        assert_eq!(
+            error("# vertex\nv 1 0\n").kind,
+            ObjErrorKind::MissingComponent
+        );
+        assert_eq!(
+            error("f 1/2/3/4 1 1\n"),
+            ObjError::new(1, "1/2/3/4", ObjErrorKind::InvalidFace)
+        );
+
+        let obj = WavefrontObj::from_reader("v 0 0 0\nv 1 0 0\nf 1 2 3\n".as_bytes(), 1.0).unwrap();
+        assert_eq!(
+            obj.make_triangles(None, false, false, false).unwrap_err(),
+            ObjError::new(3, "3", ObjErrorKind::IndexOutOfRange)
+        );
+        let obj = WavefrontObj::from_reader("v 0 0 0\nf 1 1 1\n".as_bytes(), 1.0).unwrap();
+        assert_eq!(
+            obj.make_triangles(None, true, false, false)
+                .unwrap_err()
+                .kind,
+            ObjErrorKind::MissingUv
+        );
+    }
+
+    #[test]
+    fn relative_indices() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\r\nf -3 -2 -1\nv 5 5 5\n\tf\t1  -3\t-1\n";
+        let obj = WavefrontObj::from_reader(obj.as_bytes(), 1.0).unwrap();
+        let triangles = obj.make_triangles(None, false, false, false).unwrap();
+
+        assert_eq!(triangles[0].p2.1, 1.0);
+        // Relative to the vertices read before the face, not all of them
+        assert_eq!(triangles[1].p1.0, 1.0);
+        assert_eq!(triangles[1].p2.0, 5.0);
+
+        assert_eq!(
+            WavefrontObj::from_reader("v 0 0 0\nf 1 1 -2\n".as_bytes(), 1.0).unwrap_err(),
+            ObjError::new(2, "-2", ObjErrorKind::IndexOutOfRange)
+        );
+    }
+
+    #[test]
+    fn parts() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\no body\ns 1\nf 1 2 3\ng wheel\ns off\nf 1 2 3\no body\nf 1 2 3\n";
+        let obj = WavefrontObj::from_reader(obj.as_bytes(), 1.0).unwrap();
+
+        assert_eq!(obj.part_names(), vec!["default", "body", "wheel"]);
+        let smoothing: Vec<_> = obj.faces.iter().map(|f| f.smoothing_group).collect();
+        assert_eq!(smoothing, vec![0, 1, 0, 0]);
+
+        // Both faces of "body" end up next to each other
+        let (triangles, parts) = obj.make_parts(None, false, false, false).unwrap();
+        assert_eq!(triangles.len(), 4);
+        assert_eq!(parts[1].name, "body");
+        assert_eq!(parts[1].triangles, 1..3);
+        assert_eq!(parts[2].triangles, 3..4);
+    }
+
+    #[test]
+    fn generated_normals() {
+        // Two faces folded along the x axis, smoothed unless `s off`
+        let faces = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 2 1 4\n";
+        let normal = |obj: String| {
+            let obj = WavefrontObj::from_reader(obj.as_bytes(), 1.0)
+                .unwrap()
+                .with_crease_angle(Degrees(100.0));
+            let triangles = obj.make_triangles(None, false, true, false).unwrap();
+            triangles[0].normals.as_ref().unwrap()[0].clone()
+        };
+
+        assert!(normal(faces.to_string()).1 > 0.5);
+        assert_eq!(normal(format!("s off\n{}", faces)).1, 0.0);
+        assert!(normal(format!("s 1\n{}", faces)).1 > 0.5);
+
+        // Neither read nor generated when they aren't asked for
+        let obj = format!("vn 0 0 1\n{}", faces.replace("f 1 2 3", "f 1//1 2//1 3//1"));
+        let obj = WavefrontObj::from_reader(obj.as_bytes(), 1.0).unwrap();
+        let triangles = obj.make_triangles(None, false, false, false).unwrap();
+        assert!(triangles.iter().all(|t| t.normals.is_none()));
+        let (triangles, _) = obj.make_parts(None, false, false, false).unwrap();
+        assert!(triangles.iter().all(|t| t.normals.is_none()));
     }
 }